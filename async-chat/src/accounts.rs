@@ -0,0 +1,110 @@
+//! 注册账号：昵称 + 加盐密码哈希，存在数据目录下的文本文件里
+//!
+//! 每行一个账号：`<昵称>:<盐 hex>:<PBKDF2-SHA256 hex>`，只追加不改写。
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use sha2::Sha256;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{self, AsyncWriteExt};
+
+const ACCOUNTS_FILE: &str = "accounts.txt";
+const PBKDF2_ROUNDS: u32 = 100_000; // 哈希迭代次数，故意慢一点
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// 一个账号的凭据
+#[derive(Clone)]
+pub struct Credential {
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+}
+
+impl Credential {
+    /// 随机生成盐并计算哈希（CPU 密集，调用方应放到 spawn_blocking 里）
+    pub fn new(password: &str) -> io::Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::fill(&mut salt).map_err(io::Error::other)?;
+        Ok(Credential { salt, hash: derive(password, &salt) })
+    }
+
+    /// 校验密码（同样是 CPU 密集）
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = derive(password, &self.salt);
+        // 逐字节异或再汇总，避免按前缀提前返回
+        hash.iter().zip(self.hash.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+
+    fn to_line(&self, name: &str) -> String {
+        format!("{name}:{}:{}\n", to_hex(&self.salt), to_hex(&self.hash))
+    }
+
+    fn parse(line: &str) -> Option<(String, Self)> {
+        let mut it = line.trim().split(':');
+        let name = it.next()?.to_string();
+        let salt = from_hex(it.next()?)?.try_into().ok()?;
+        let hash = from_hex(it.next()?)?.try_into().ok()?;
+        Some((name, Credential { salt, hash }))
+    }
+}
+
+fn derive(password: &str, salt: &[u8]) -> [u8; HASH_LEN] {
+    let mut out = [0u8; HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, PBKDF2_ROUNDS, &mut out);
+    out
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 所有已注册账号（内存表 + 追加写文件）
+pub struct Accounts {
+    path: PathBuf,
+    users: HashMap<String, Credential>,
+}
+
+impl Accounts {
+    /// 从数据目录加载；文件不存在就是空表
+    pub async fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(ACCOUNTS_FILE);
+        let users = match fs::read_to_string(&path).await {
+            Ok(text) => text.lines().filter_map(Credential::parse).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Accounts { path, users })
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.users.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Credential> {
+        self.users.get(name).cloned()
+    }
+
+    /// 新增账号并落盘
+    pub async fn insert(&mut self, name: &str, cred: Credential) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(cred.to_line(name).as_bytes()).await?;
+        file.flush().await?;
+        self.users.insert(name.to_string(), cred);
+        Ok(())
+    }
+}
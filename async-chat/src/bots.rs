@@ -0,0 +1,113 @@
+//! 自带的机器人和小工具命令：掷骰子、定时提醒。也是写新机器人的例子。
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::time::{sleep, Duration};
+
+use crate::commands::{Bot, BotHandle, BoxFuture, Command, Session};
+use crate::moderation::{format_duration, parse_duration};
+use crate::{system, Peer};
+
+const DICE_MAX: u32 = 20;          // 一次最多掷几个
+const SIDES_MAX: u32 = 1000;       // 每个最多几面
+const REMIND_PENDING: usize = 5;   // 每个连接最多挂几个提醒
+const REMIND_MAX: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 掷骰子：房间里有人说 `!roll` 或 `!roll 2d6`，在房间里报结果
+pub struct Dice;
+
+impl Bot for Dice {
+    fn name(&self) -> &'static str {
+        "dice"
+    }
+
+    fn on_chat<'a>(&'a self, bot: &'a BotHandle, room: &'a str, from: &'a str, text: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(arg) = text.strip_prefix("!roll") else { return };
+            if !arg.is_empty() && !arg.starts_with(char::is_whitespace) {
+                return; // 形如 `!rolling`
+            }
+            let reply = match parse_dice(arg.trim()) {
+                Some((count, sides)) => match roll(count, sides) {
+                    Some(rolls) => {
+                        let total: u32 = rolls.iter().sum();
+                        let each: Vec<String> = rolls.iter().map(u32::to_string).collect();
+                        format!("{from} rolled {count}d{sides}: {} = {total}", each.join(" + "))
+                    }
+                    None => "The dice are stuck, try again".to_string(),
+                },
+                None => format!("Usage: !roll [NdM], e.g. !roll 2d6 (up to {DICE_MAX} dice, 2-{SIDES_MAX} sides)"),
+            };
+            bot.say(room, &reply).await;
+        })
+    }
+}
+
+/// 解析 `NdM`，`dM` 就是一个，空的就是 1d6
+fn parse_dice(s: &str) -> Option<(u32, u32)> {
+    if s.is_empty() {
+        return Some((1, 6));
+    }
+    let s = s.to_ascii_lowercase();
+    let (count, sides) = s.split_once('d')?;
+    let count = if count.is_empty() { 1 } else { count.parse().ok()? };
+    let sides = sides.parse().ok()?;
+    ((1..=DICE_MAX).contains(&count) && (2..=SIDES_MAX).contains(&sides)).then_some((count, sides))
+}
+
+fn roll(count: u32, sides: u32) -> Option<Vec<u32>> {
+    (0..count).map(|_| getrandom::u32().ok().map(|r| r % sides + 1)).collect()
+}
+
+/// `/remind <duration> <text>`：到时间只提醒本人；断开了就作废
+#[derive(Default)]
+pub struct Remind {
+    pending: Arc<Mutex<HashMap<Peer, usize>>>, // 每个连接还没到时间的提醒数
+}
+
+impl Command for Remind {
+    fn name(&self) -> &'static str {
+        "/remind"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/remind <duration> <text>"
+    }
+
+    fn about(&self) -> &'static str {
+        "remind yourself later, e.g. /remind 10m tea (up to 24h)"
+    }
+
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let (after, text) = arg.split_once(char::is_whitespace).ok_or_else(|| self.usage_error())?;
+            let after = parse_duration(after).and_then(Result::ok).filter(|d| *d <= REMIND_MAX);
+            let after = after.ok_or_else(|| self.usage_error())?;
+            {
+                let mut pending = self.pending.lock().unwrap();
+                let n = pending.entry(s.peer).or_default();
+                if *n >= REMIND_PENDING {
+                    return Err(format!("You already have {REMIND_PENDING} reminders pending"));
+                }
+                *n += 1;
+            }
+            let (peer, out, text) = (s.peer, s.out.clone(), text.trim().to_string());
+            let pending = self.pending.clone();
+            tokio::spawn(async move {
+                sleep(after).await;
+                let _ = out.send(system(format!("Reminder: {text}")));
+                let mut pending = pending.lock().unwrap();
+                if let Some(n) = pending.get_mut(&peer) {
+                    *n -= 1;
+                    if *n == 0 {
+                        pending.remove(&peer);
+                    }
+                }
+            });
+            s.reply(format!("I will remind you in {}", format_duration(after)));
+            Ok(())
+        })
+    }
+}
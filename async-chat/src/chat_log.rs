@@ -0,0 +1,235 @@
+//! 聊天记录持久化：追加写日志文件 + 按大小轮转
+//!
+//! 每行一条记录：`<unix 秒>\t<房间>\t<发送者>\t<JSON 帧>`，帧序列化后不含换行和 tab。
+//! JSON 帧带着消息 ID 和时间；加 ID 之前写的旧记录没有，回放时按顺序补上。
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{self, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use chrono::DateTime;
+
+use crate::protocol::{Envelope, ServerFrame, Stamp};
+
+const LOG_FILE: &str = "chat.log";
+const LOG_MAX_BYTES: u64 = 1024 * 1024; // 超过 1 MiB 就轮转
+const LOG_KEEP: usize = 3;               // 保留 chat.log.1 ~ chat.log.3
+
+/// 一条日志记录
+pub struct LogRecord {
+    pub ts: u64,
+    pub room: String,
+    pub sender: String,
+    pub frame: Envelope,
+}
+
+impl LogRecord {
+    /// 以当前时间生成记录
+    pub fn now(room: &str, sender: &str, frame: &Envelope) -> Self {
+        LogRecord { ts: unix_now(), room: room.to_string(), sender: sender.to_string(), frame: frame.clone() }
+    }
+
+    fn to_line(&self) -> String {
+        let frame = serde_json::to_string(&self.frame).expect("ServerFrame always serializes");
+        format!("{}\t{}\t{}\t{}\n", self.ts, self.room, self.sender, frame)
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let mut it = s.splitn(4, '\t');
+        let ts = it.next()?.parse().ok()?;
+        let room = it.next()?.to_string();
+        let sender = it.next()?.to_string();
+        let frame = serde_json::from_str(it.next()?).ok()?;
+        Some(LogRecord { ts, room, sender, frame })
+    }
+}
+
+/// 当前 unix 秒
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// 追加写的日志文件
+pub struct ChatLog {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl ChatLog {
+    /// 打开（必要时创建）数据目录下的日志文件
+    pub async fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir).await?;
+        let file = OpenOptions::new().create(true).append(true).open(dir.join(LOG_FILE)).await?;
+        let size = file.metadata().await?.len();
+        Ok(ChatLog { dir: dir.to_path_buf(), file, size })
+    }
+
+    /// 追加一条记录，写之前检查是否需要轮转
+    pub async fn append(&mut self, rec: &LogRecord) -> io::Result<()> {
+        let line = rec.to_line();
+        if self.size > 0 && self.size + line.len() as u64 > LOG_MAX_BYTES {
+            self.rotate().await?;
+        }
+        self.file.write_all(line.as_bytes()).await?;
+        self.file.flush().await?; // tokio 的 File 自带缓冲，及时落盘
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// chat.log -> chat.log.1 -> chat.log.2 ...，最老的一份被覆盖
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush().await?;
+        for i in (1..LOG_KEEP).rev() {
+            let from = rotated_path(&self.dir, i);
+            if fs::try_exists(&from).await? {
+                fs::rename(&from, rotated_path(&self.dir, i + 1)).await?;
+            }
+        }
+        fs::rename(self.dir.join(LOG_FILE), rotated_path(&self.dir, 1)).await?;
+        self.file = OpenOptions::new().create(true).append(true).open(self.dir.join(LOG_FILE)).await?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(dir: &Path, i: usize) -> PathBuf {
+    dir.join(format!("{LOG_FILE}.{i}"))
+}
+
+/// 启动时从日志恢复的内容
+pub struct Replay {
+    pub history: HashMap<String, VecDeque<Envelope>>, // 每个房间最后 N 条
+    pub last_id: u64,                                 // 用过的最大消息 ID
+    pub topics: HashMap<String, (String, String)>,    // 房间 -> (话题, 设置者)
+}
+
+/// 启动时回放：从老到新读所有日志，每个房间保留最后 `per_room` 条，编辑和删除照样生效
+pub async fn load_recent(dir: &Path, per_room: usize) -> io::Result<Replay> {
+    let mut replay = Replay { history: HashMap::new(), last_id: 0, topics: HashMap::new() };
+    for_each_record(dir, |rec| {
+        replay.last_id = replay.last_id.max(rec.frame.stamp.map_or(0, |s| s.id));
+        if let ServerFrame::Topic { by, text, .. } = &rec.frame.frame {
+            replay.topics.insert(rec.room.clone(), (text.clone(), by.clone()));
+        }
+        let h = replay.history.entry(rec.room).or_default();
+        if revise(h, &rec.frame.frame) {
+            return;
+        }
+        if h.len() == per_room {
+            h.pop_front();
+        }
+        h.push_back(rec.frame);
+    })
+    .await?;
+    Ok(replay)
+}
+
+/// 把编辑、删除作用到历史上。返回 frame 是不是这两种（是的话它本身不进历史）
+pub fn revise(history: &mut VecDeque<Envelope>, frame: &ServerFrame) -> bool {
+    match frame {
+        ServerFrame::Edit { target, text, .. } => {
+            let original = history.iter_mut().find(|env| env.stamp.is_some_and(|s| s.id == *target));
+            if let Some(Envelope { frame: ServerFrame::Chat { text: old, .. } | ServerFrame::Action { text: old, .. }, .. }) =
+                original
+            {
+                old.clone_from(text);
+            }
+            true
+        }
+        ServerFrame::Delete { target, .. } => {
+            history.retain(|env| env.stamp.is_none_or(|s| s.id != *target));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 历史查询：某个房间里 ID 小于 before、符合条件的消息，取最新的 limit 条
+pub struct Query {
+    pub room: String,
+    pub before: Option<u64>,
+    pub text: Option<String>, // 只搜聊天和动作的内容，已转小写
+    pub from: Option<String>, // 发送者
+    pub limit: usize,
+}
+
+impl Query {
+    fn matches(&self, rec: &LogRecord) -> bool {
+        if self.before.is_some_and(|before| rec.frame.stamp.is_none_or(|s| s.id >= before)) {
+            return false;
+        }
+        self.from.as_ref().is_none_or(|from| rec.sender == *from)
+    }
+
+    fn matches_text(&self, frame: &ServerFrame) -> bool {
+        match (&self.text, frame) {
+            (None, _) => true,
+            (Some(text), ServerFrame::Chat { text: body, .. } | ServerFrame::Action { text: body, .. }) => {
+                body.to_lowercase().contains(text)
+            }
+            (Some(_), _) => false,
+        }
+    }
+}
+
+/// 在全部日志里查询，结果从旧到新；第二个值表示更早还有没返回的
+pub async fn search(dir: &Path, query: &Query) -> io::Result<(Vec<Envelope>, bool)> {
+    // 编辑和删除作用在更早的消息上，所以先收下整个房间，最后再按文字过滤、取最新的几条
+    let mut found = VecDeque::new();
+    for_each_record(dir, |rec| {
+        if rec.room == query.room && !revise(&mut found, &rec.frame.frame) && query.matches(&rec) {
+            found.push_back(rec.frame);
+        }
+    })
+    .await?;
+    let mut hits: Vec<Envelope> = found.into_iter().filter(|env| query.matches_text(&env.frame)).collect();
+    let more = hits.len() > query.limit;
+    let hits = hits.split_off(hits.len().saturating_sub(query.limit));
+    Ok((hits, more))
+}
+
+/// 从老到新遍历所有日志（轮转出去的也算）
+async fn for_each_record(dir: &Path, mut f: impl FnMut(LogRecord)) -> io::Result<()> {
+    let mut last_id = 0;
+    let mut files: Vec<PathBuf> = (1..=LOG_KEEP).rev().map(|i| rotated_path(dir, i)).collect();
+    files.push(dir.join(LOG_FILE));
+
+    for path in files {
+        let text = match fs::read_to_string(&path).await {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        // 坏行（写到一半崩溃、旧格式）直接跳过
+        for mut rec in text.lines().filter_map(LogRecord::parse) {
+            // 旧记录没有 ID：接着前面的编号，时间用记录里的 unix 秒
+            let stamp = rec.frame.stamp.get_or_insert_with(|| Stamp {
+                id: last_id + 1,
+                ts: DateTime::from_timestamp(rec.ts as i64, 0).unwrap_or_default(),
+            });
+            last_id = last_id.max(stamp.id);
+            f(rec);
+        }
+    }
+    Ok(())
+}
+
+/// 日志写任务：独占文件，串行写入收到的记录；所有 sender 关闭后退出
+pub fn spawn_writer(mut log: ChatLog) -> (mpsc::UnboundedSender<LogRecord>, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<LogRecord>();
+    let handle = tokio::spawn(async move {
+        while let Some(rec) = rx.recv().await {
+            if let Err(e) = log.append(&rec).await {
+                eprintln!("! Chat log write error: {e}");
+            }
+        }
+    });
+    (tx, handle)
+}
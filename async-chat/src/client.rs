@@ -1,78 +1,667 @@
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use std::{io::BufRead, path::PathBuf, process::ExitCode, sync::Arc};
+use chrono::Local;
+use regex::Regex;
+use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+mod files;
+mod tui;
+use files::{is_file_frame, Files};
+use async_chat::protocol::{describe_members, ClientFrame, Envelope, ServerFrame, PROTO_JSON};
+
+/// 断线重连的退避：从 BACKOFF_MIN 开始每次翻倍，最多 BACKOFF_MAX
+const BACKOFF_MIN: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// 连上超过这么久才算稳定，下次断线时退避从头算（避免连上就被踢的情况下狂连）
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+/// 退出时最多等服务器多久（让它处理完最后几行再断开）
+const LINGER: Duration = Duration::from_secs(2);
+/// 脚本模式默认每条隔多久发（服务器默认每秒限 5 行）
+const SCRIPT_DELAY: Duration = Duration::from_millis(250);
+/// --wait 没等到时的退出码
+const EXIT_NO_MATCH: u8 = 2;
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
-    let addr = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:7000".to_string());
-    let stream = TcpStream::connect(&addr).await?;
-    println!("Connected to {addr}");
-    println!("Commands:");
-    println!("  /nick <name>      set or change nickname");
-    println!("  /w <name> <msg>   whisper");
-    println!("Type your nickname first (or just Enter to use address):");
-
-    let (reader, writer) = stream.into_split();
-
-    // 出站写通道：统一把需要发送的文本（不含换行）发到写泵
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
-
-    // 写泵任务：独占 writer，把收到的每条消息加上 \n 后写出
-    let write_task = tokio::spawn(async move {
-        use tokio::io::AsyncWriteExt;
-        let mut w = writer;
-        while let Some(line) = rx.recv().await {
-            if w.write_all(line.as_bytes()).await.is_err() { break; }
-            if w.write_all(b"\n").await.is_err() { break; }
+async fn main() -> io::Result<ExitCode> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let stream = connect(&args).await?;
+
+    // 连接循环只管收发，显示交给界面：纯文本模式逐行打印（方便脚本），--tui 是全屏界面
+    let (input_tx, input) = mpsc::unbounded_channel::<String>();
+    let (screen, mut shown) = mpsc::unbounded_channel::<Show>();
+    if args.tui {
+        let chat = tokio::spawn(chat(args, stream, input, screen));
+        // 界面退出时丢掉 input_tx，连接循环就当用户要走
+        let result = tui::run(shown, input_tx).await;
+        let _ = chat.await;
+        return result.map(|()| ExitCode::SUCCESS);
+    }
+
+    // 输入都在普通线程里阻塞读：整个进程共用，重连时不丢输入。
+    // tokio 的 stdin 会让运行时退出时一直等着那次读
+    if args.scripted() {
+        feed_script(&args, input_tx.clone())?;
+    } else {
+        let input_tx = input_tx.clone();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if input_tx.send(line).is_err() { break; }
+            }
+        });
+    }
+    // --wait 的时候输入发完了也不走，等到匹配或超时
+    let _keep_open = args.wait.is_some().then_some(input_tx);
+
+    // 收到的消息打到 stdout，客户端自己的提示打到 stderr，脚本只看 stdout 就行
+    let wait = args.wait.clone();
+    let printer = tokio::spawn(async move {
+        while let Some(item) = shown.recv().await {
+            let (line, live) = match item {
+                Show::Frame(env) => {
+                    println!("{}", render_envelope(&env));
+                    (render(&env.frame), !matches!(env.frame, ServerFrame::History { .. }))
+                }
+                Show::Raw(line) => {
+                    println!("{line}");
+                    (line, true)
+                }
+                Show::Note(line) => {
+                    eprintln!("{line}");
+                    continue;
+                }
+            };
+            // 只看新消息，历史回放里的旧话不算
+            if live && wait.as_ref().is_some_and(|re| re.is_match(&line)) {
+                return true;
+            }
         }
+        false
     });
 
-    // 启动时读取一行昵称并发送 /nick（如果非空）
-    {
-        let mut stdin = BufReader::new(tokio::io::stdin()).lines();
-        if let Ok(Some(input)) = stdin.next_line().await {
-            if !input.trim().is_empty() {
-                let _ = tx.send(format!("/nick {}", input.trim()));
-            } else {
-                println!("(empty -> use default addr as name)");
+    let Some(limit) = args.wait.as_ref().map(|_| args.timeout) else {
+        let ended = chat(args, stream, input, screen).await;
+        let _ = printer.await;
+        return Ok(if matches!(ended, Ended::Refused(_)) { ExitCode::FAILURE } else { ExitCode::SUCCESS });
+    };
+    tokio::spawn(chat(args, stream, input, screen));
+    let matched = match limit {
+        Some(limit) => matches!(timeout(limit, printer).await, Ok(Ok(true))),
+        None => matches!(printer.await, Ok(true)),
+    };
+    if !matched {
+        eprintln!("** No line matched before {}", if limit.is_some() { "the timeout" } else { "the connection closed" });
+    }
+    Ok(if matched { ExitCode::SUCCESS } else { ExitCode::from(EXIT_NO_MATCH) })
+}
+
+/// 脚本模式的输入：先 --send 的几条，再 --file 的每一行（`-` 是 stdin），每条之间隔 --delay
+fn feed_script(args: &ClientArgs, input_tx: mpsc::UnboundedSender<String>) -> io::Result<()> {
+    let file: Option<Box<dyn std::io::Read + Send>> = match args.file.as_deref() {
+        None => None,
+        Some(path) if path.as_os_str() == "-" => Some(Box::new(std::io::stdin())),
+        Some(path) => Some(Box::new(
+            std::fs::File::open(path).map_err(|e| invalid_arg(&format!("{}: {e}", path.display())))?,
+        )),
+    };
+    let (sends, delay) = (args.send.clone(), args.delay);
+    std::thread::spawn(move || {
+        let lines = sends.into_iter().map(Ok).chain(file.into_iter().flat_map(|f| std::io::BufReader::new(f).lines()));
+        for (i, line) in lines.enumerate() {
+            let Ok(line) = line else { break };
+            if i > 0 {
+                std::thread::sleep(delay);
+            }
+            if input_tx.send(line).is_err() { break; }
+        }
+    });
+    Ok(())
+}
+
+/// 连接循环交给界面显示的东西
+enum Show {
+    /// 服务器发来的一帧
+    Frame(Envelope),
+    /// 不认识的行（比如不支持 JSON 的旧服务器、被封时的拒绝提示），原样显示
+    Raw(String),
+    /// 客户端自己的提示：帮助、断线、重连……
+    Note(String),
+}
+
+type Screen = mpsc::UnboundedSender<Show>;
+
+fn note(screen: &Screen, text: impl Into<String>) {
+    let _ = screen.send(Show::Note(text.into()));
+}
+
+/// 整个会话：定下昵称，跑连接，断了就退避重连，直到用户退出（返回 Quit）或被拒（Refused）
+async fn chat(
+    args: ClientArgs,
+    stream: Box<dyn ChatStream>,
+    mut input: mpsc::UnboundedReceiver<String>,
+    screen: Screen,
+) -> Ended {
+    let addr = args.addr.clone();
+    let mode = if args.tls { " (TLS)" } else { "" };
+    note(&screen, format!("Connected to {addr}{mode}"));
+    if !args.scripted() {
+        for line in HELP {
+            note(&screen, *line);
+        }
+    }
+
+    // 昵称：--nick 给了就直接用；脚本模式没给就用地址；否则第一行输入就是昵称
+    let mut resume = Resume::default();
+    let mut queued = Vec::new();
+    let nick = match &args.nick {
+        Some(nick) => Some(nick.clone()),
+        None if args.scripted() => None,
+        None => {
+            note(&screen, "Type your nickname first (or just Enter to use address):");
+            match input.recv().await {
+                Some(nick) if !nick.trim().is_empty() => Some(nick.trim().to_string()),
+                Some(_) => {
+                    note(&screen, "(empty -> use default addr as name)");
+                    None
+                }
+                None => return Ended::Quit,
             }
+        }
+    };
+    if let Some(nick) = nick {
+        let line = format!("/nick {nick}");
+        resume.note_input(&line);
+        queued.push(line);
+    }
 
-            // 后续持续读取 stdin，转发到服务器
-            let tx_stdin = tx.clone();
-            tokio::spawn(async move {
-                let mut stdin = BufReader::new(tokio::io::stdin()).lines();
-                while let Ok(Some(line)) = stdin.next_line().await {
-                    if tx_stdin.send(line).is_err() { break; }
+    let mut stream = Some(stream);
+    let mut delay = BACKOFF_MIN;
+    loop {
+        let conn = match stream.take() {
+            Some(conn) => conn,
+            None => {
+                // 退避等待期间用户照样可以输入，先攒着，连上后再发
+                let deadline = Instant::now() + delay;
+                note(&screen, format!("** Reconnecting to {addr} in {}...", describe(delay)));
+                loop {
+                    tokio::select! {
+                        _ = sleep_until(deadline) => break,
+                        line = input.recv() => match line {
+                            Some(line) => {
+                                note(&screen, "** (not connected; will send after reconnecting)");
+                                resume.note_input(&line);
+                                queued.push(line);
+                            }
+                            None => return Ended::Quit,
+                        },
+                    }
                 }
-            });
-        } else {
-            // 没读到昵称输入，也继续进入正常循环
-            let tx_stdin = tx.clone();
-            tokio::spawn(async move {
-                let mut stdin = BufReader::new(tokio::io::stdin()).lines();
-                while let Ok(Some(line)) = stdin.next_line().await {
-                    if tx_stdin.send(line).is_err() { break; }
+                delay = (delay * 2).min(BACKOFF_MAX);
+                match connect(&args).await {
+                    Ok(conn) => {
+                        let from = resume.last_id.map(|id| format!(", catching up after #{id}")).unwrap_or_default();
+                        note(&screen, format!("** Reconnected to {addr}{from}"));
+                        let mut replay = resume.replay();
+                        replay.append(&mut queued);
+                        queued = replay;
+                        conn
+                    }
+                    Err(e) => {
+                        note(&screen, format!("** Reconnect failed: {e}"));
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let started = Instant::now();
+        let files = Files::new(args.download_dir.clone());
+        match run(conn, &mut resume, &mut input, &screen, files, std::mem::take(&mut queued)).await {
+            Ended::Quit => return Ended::Quit,
+            Ended::Refused(why) => {
+                note(&screen, format!("** Disconnected ({why}); not reconnecting"));
+                return Ended::Refused(why);
+            }
+            Ended::Lost => {
+                note(&screen, "** Connection lost");
+                if started.elapsed() >= STABLE_AFTER {
+                    delay = BACKOFF_MIN;
                 }
-            });
+            }
         }
     }
+}
 
-    // 读服务器：遇到 PING 立即通过通道回 PONG，其余打印
-    let mut server_reader = BufReader::new(reader).lines();
-    while let Ok(Some(line)) = server_reader.next_line().await {
-        if line == "PING" {
-            // println!("[heartbeat] <- PING");
-            let _ = tx.send("PONG".to_string());
-            // println!("[heartbeat] -> PONG");
-            continue;
+const HELP: &[&str] = &[
+    "Commands:",
+    "  /nick <name>      set or change nickname",
+    "  /w <name> <msg>   whisper",
+    "  /join <room>      switch to a room (default #lobby)",
+    "  /leave            go back to #lobby",
+    "  /rooms            list rooms and member counts",
+    "  /me <action>      describe what you are doing",
+    "  /topic [text]     show or set the room topic",
+    "  /edit <id> <text>, /delete <id>   change or remove one of your recent messages",
+    "  /who [room]       list who is in this (or another) room",
+    "  /whois <nick>     connect time, idle time and away message",
+    "  /away [msg]       mark yourself away (whispers get an auto-reply); /back to return",
+    "  /history [n]      older messages in this room (/history before <id> [n] to page back)",
+    "  /search <text> [from:<nick>] [before:<id>]   search this room's history",
+    "  /register <name> <password>   reserve a nickname",
+    "  /login <name> <password>      log in to a registered nickname",
+    "  /remind <duration> <text>   remind yourself later, e.g. /remind 10m tea",
+    "  /send <nick> <path>   offer a file; they /accept [n] or /reject [n]; /cancel [n] stops a transfer",
+    "  !roll [NdM]       ask the dice bot to roll, e.g. !roll 2d6",
+    "  /help             full command list from the server; //text sends text starting with '/'",
+    "  operators: /kick <nick> [reason], /mute <nick> [10m], /ban <nick|ip> [1h], /unban <nick|ip>",
+    "If the connection drops, the client reconnects and picks up where it left off.",
+];
+
+/// 一条连接（或整个会话）是怎么结束的
+enum Ended {
+    /// 输入关了（stdin、--file 到头或界面退出），用户要走
+    Quit,
+    /// 断线（服务器重启、网络问题……），该重连
+    Lost,
+    /// 被踢或被封，重连也没用
+    Refused(String),
+}
+
+/// 重连后要恢复的会话。按用户输入乐观地记下来（命令失败的话重连后会再失败一次，无妨）
+#[derive(Default)]
+struct Resume {
+    nick: Option<String>,
+    login: Option<String>, // 最后一次 /login（或 /register）的名字和密码，重连后重新登录
+    room: Option<String>,  // 不在大厅时所在的房间
+    last_id: Option<u64>,  // 见过的最大消息 ID，重连时 /since 它
+}
+
+impl Resume {
+    /// 看一眼用户输入，记下昵称、登录和房间
+    fn note_input(&mut self, line: &str) {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("/nick"), Some(nick), _) => self.nick = Some(nick.to_string()),
+            (Some("/login" | "/register"), Some(name), Some(_)) => {
+                let password = line.trim().splitn(3, char::is_whitespace).nth(2).unwrap_or("").trim();
+                self.login = Some(format!("{name} {password}"));
+                self.nick = Some(name.to_string());
+            }
+            (Some("/join"), Some(room), _) => {
+                let room = room.trim_start_matches('#').to_ascii_lowercase();
+                self.room = (room != "lobby").then_some(room);
+            }
+            (Some("/leave"), _, _) => self.room = None,
+            _ => {}
+        }
+    }
+
+    /// 重连后按顺序发的行：先 /since（必须在第一行之前），再昵称或登录，最后回到原来的房间
+    fn replay(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(id) = self.last_id {
+            lines.push(format!("/since {id}"));
+        }
+        let account = self.login.as_deref().and_then(|l| l.split_whitespace().next());
+        // 注册过的昵称要登录后才能用，所以这种情况第一行先空着（用地址名），登录时再换过来
+        match &self.nick {
+            Some(nick) if account.is_none() => lines.push(format!("/nick {nick}")),
+            _ => lines.push(String::new()),
+        }
+        if let Some(login) = &self.login {
+            lines.push(format!("/login {login}"));
+            if let Some(nick) = self.nick.as_deref().filter(|n| Some(*n) != account) {
+                lines.push(format!("/nick {nick}"));
+            }
+        }
+        if let Some(room) = &self.room {
+            lines.push(format!("/join {room}"));
+        }
+        lines
+    }
+}
+
+/// 跑一条连接：先发 queued 里的行，然后转发用户输入、显示服务器消息，直到断开
+async fn run(
+    stream: Box<dyn ChatStream>,
+    resume: &mut Resume,
+    input: &mut mpsc::UnboundedReceiver<String>,
+    screen: &Screen,
+    mut files: Files,
+    queued: Vec<String>,
+) -> Ended {
+    let (reader, writer) = io::split(stream);
+
+    // 出站写通道：统一把需要发送的帧发到写泵
+    let (tx, mut rx) = mpsc::unbounded_channel::<ClientFrame>();
+
+    // 写泵任务：独占 writer，先协商 JSON 协议，再把每帧编码成一行写出
+    let write_task = tokio::spawn(async move {
+        use tokio::io::AsyncWriteExt;
+        let mut w = writer;
+        if w.write_all(format!("{PROTO_JSON}\n").as_bytes()).await.is_err() { return; }
+        while let Some(frame) = rx.recv().await {
+            let mut line = serde_json::to_string(&frame).expect("ClientFrame always serializes");
+            line.push('\n');
+            if w.write_all(line.as_bytes()).await.is_err() { return; }
+        }
+        // 发完了：只关写的一半，服务器读到 EOF 会处理完前面的行再断开
+        let _ = w.shutdown().await;
+    });
+
+    // 重连时补发的历史里，ID 不超过这个的之前已经显示过；用户再输入之前都跳过（第一次连接时还是 None）
+    let mut seen_upto = resume.last_id;
+    for line in queued {
+        if !files.command(&line, &tx, screen).await {
+            let _ = tx.send(line_frame(line));
         }
-        println!("{}", line);
     }
+    // 用户要走时丢掉 tx 关闭写泵，再把服务器剩下的话读完，最多等 LINGER。
+    // 直接关连接的话，收缓冲里还有没读的数据，内核会发 RST，服务器可能来不及处理最后几行
+    let mut tx = Some(tx);
+    let mut quit_by = None;
+
+    // 读服务器：遇到 Ping 立即通过通道回 Pong，其余交给界面；同时转发用户输入
+    let mut server_reader = BufReader::new(reader).lines();
+    let mut last_error = None;
+    let ended = loop {
+        tokio::select! {
+            res = server_reader.next_line() => {
+                let Ok(Some(line)) = res else {
+                    if quit_by.is_some() {
+                        break Ended::Quit;
+                    }
+                    break match last_error.filter(|e: &String| is_refusal(e)) {
+                        Some(why) => Ended::Refused(why),
+                        None => Ended::Lost,
+                    };
+                };
+                match serde_json::from_str::<Envelope>(&line) {
+                    Ok(Envelope { frame: ServerFrame::Ping, .. }) => {
+                        if let Some(tx) = &tx {
+                            let _ = tx.send(ClientFrame::Pong);
+                        }
+                    }
+                    // 传文件的帧自己处理，只显示进度提示
+                    Ok(env) if is_file_frame(&env.frame) => {
+                        if let Some(tx) = &tx {
+                            files.on_frame(env.frame, tx, screen).await;
+                        }
+                    }
+                    Ok(env) => {
+                        if let Some(stamp) = env.stamp {
+                            let replayed = matches!(env.frame, ServerFrame::History { .. });
+                            if replayed && seen_upto.is_some_and(|id| stamp.id <= id) {
+                                continue;
+                            }
+                            resume.last_id = Some(resume.last_id.map_or(stamp.id, |id| id.max(stamp.id)));
+                        }
+                        match &env.frame {
+                            ServerFrame::Error { text } => last_error = Some(text.clone()),
+                            // 空闲超时的通知是 System 帧，也记下来，断开后就不重连了
+                            ServerFrame::System { text } if text.starts_with("Idle timeout") => {
+                                last_error = Some(text.clone())
+                            }
+                            _ => {}
+                        }
+                        let _ = screen.send(Show::Frame(env));
+                    }
+                    Err(_) => {
+                        last_error = Some(line.clone());
+                        let _ = screen.send(Show::Raw(line));
+                    }
+                }
+            }
+            line = input.recv(), if tx.is_some() => match line {
+                Some(line) => {
+                    seen_upto = None;
+                    resume.note_input(&line);
+                    if let Some(tx) = &tx
+                        && !files.command(&line, tx, screen).await
+                    {
+                        let _ = tx.send(line_frame(line));
+                    }
+                }
+                None => {
+                    // 发送任务也拿着写通道，先停掉，写泵才会收尾
+                    files.abandon(screen).await;
+                    tx = None;
+                    quit_by = Some(Instant::now() + LINGER);
+                }
+            },
+            _ = sleep_until(quit_by.unwrap_or_else(Instant::now)), if quit_by.is_some() => break Ended::Quit,
+        }
+    };
 
-    // 服务器读循环结束：关闭写泵
+    // 关闭写泵；Quit 时让它把剩下的写完
+    files.abandon(screen).await;
     drop(tx);
     let _ = write_task.await;
-    Ok(())
+    ended
+}
+
+/// 服务器断开前最后一句话是不是踢出、封禁或空闲超时；空闲超时重连的话，过一个超时又被踢，没完没了
+fn is_refusal(text: &str) -> bool {
+    text.starts_with("You were kicked")
+        || text.starts_with("You were banned")
+        || text.contains("You are banned")
+        || text.contains("Idle timeout:")
+}
+
+/// 退避时间写成 `500ms` / `4s`
+fn describe(d: Duration) -> String {
+    if d < Duration::from_secs(1) { format!("{}ms", d.as_millis()) } else { format!("{}s", d.as_secs()) }
+}
+
+/// 命令行参数：`[addr] [--tls] [--ca <pem>] [--insecure] [--tui] [--nick <name>] [--download-dir <dir>]`，
+/// 加上脚本用的 `[--send <text>]... [--file <path|->] [--delay <ms>] [--wait <regex>] [--timeout <secs>]`。
+///
+/// 脚本模式下退出码：0 正常（或等到了匹配的行），1 出错或被踢、被封，2 超时或断开前没等到匹配的行
+struct ClientArgs {
+    addr: String,
+    tls: bool,
+    ca: Option<PathBuf>,
+    insecure: bool,
+    tui: bool,
+    nick: Option<String>,
+    send: Vec<String>,     // 要发的消息，发完就走
+    file: Option<PathBuf>, // 一行一条地发，读到 EOF 为止
+    delay: Duration,       // 脚本里每两条之间隔多久，免得触发服务器的刷屏限制
+    wait: Option<Regex>,   // 等到一条匹配的新消息再走
+    timeout: Option<Duration>,
+    download_dir: PathBuf, // 收到的文件存这里
+}
+
+impl ClientArgs {
+    /// 不交互：不打帮助、不问昵称，输入来自参数或文件而不是 stdin
+    fn scripted(&self) -> bool {
+        !self.send.is_empty() || self.file.is_some() || self.wait.is_some()
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> io::Result<ClientArgs> {
+    let mut parsed = ClientArgs {
+        addr: "127.0.0.1:7000".to_string(),
+        tls: false,
+        ca: None,
+        insecure: false,
+        tui: false,
+        nick: None,
+        send: Vec::new(),
+        file: None,
+        delay: SCRIPT_DELAY,
+        wait: None,
+        timeout: None,
+        download_dir: PathBuf::from("downloads"),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_arg(&format!("{arg} needs a value")));
+        match arg.as_str() {
+            "--tls" => parsed.tls = true,
+            "--insecure" => parsed.insecure = true,
+            "--tui" => parsed.tui = true,
+            "--ca" => parsed.ca = Some(PathBuf::from(value()?)),
+            "--nick" => parsed.nick = Some(value()?),
+            "--send" => parsed.send.push(value()?),
+            "--file" => parsed.file = Some(PathBuf::from(value()?)),
+            "--download-dir" => parsed.download_dir = PathBuf::from(value()?),
+            "--delay" => parsed.delay = Duration::from_millis(parse_number(&arg, &value()?)?),
+            "--wait" => {
+                let pattern = value()?;
+                parsed.wait = Some(Regex::new(&pattern).map_err(|e| invalid_arg(&format!("--wait: {e}")))?);
+            }
+            "--timeout" => parsed.timeout = Some(Duration::from_secs(parse_number(&arg, &value()?)?)),
+            other if other.starts_with("--") => return Err(invalid_arg(&format!("unknown argument: {other}"))),
+            _ => parsed.addr = arg,
+        }
+    }
+    // 给了 CA 或 insecure 就隐含 TLS
+    parsed.tls |= parsed.ca.is_some() || parsed.insecure;
+    if parsed.tls && parsed.ca.is_none() && !parsed.insecure {
+        return Err(invalid_arg("--tls needs --ca <pem> (or --insecure for testing)"));
+    }
+    if parsed.tui && parsed.scripted() {
+        return Err(invalid_arg("--tui can't be combined with --send, --file or --wait"));
+    }
+    if parsed.timeout.is_some() && parsed.wait.is_none() {
+        return Err(invalid_arg("--timeout only applies to --wait"));
+    }
+    Ok(parsed)
+}
+
+fn parse_number(flag: &str, value: &str) -> io::Result<u64> {
+    value.parse().map_err(|_| invalid_arg(&format!("{flag} needs a number, got '{value}'")))
+}
+
+fn invalid_arg(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+/// 明文和 TLS 连接统一成一个可读写的流
+trait ChatStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ChatStream for T {}
+
+async fn connect(args: &ClientArgs) -> io::Result<Box<dyn ChatStream>> {
+    let tcp = TcpStream::connect(&args.addr).await?;
+    if !args.tls {
+        return Ok(Box::new(tcp));
+    }
+
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+    let config = if args.insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerify(provider)))
+            .with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        let path = args.ca.as_deref().expect("checked in parse_args");
+        for cert in CertificateDer::pem_file_iter(path).map_err(|e| invalid_arg(&format!("{}: {e}", path.display())))? {
+            let cert = cert.map_err(|e| invalid_arg(&format!("{}: {e}", path.display())))?;
+            roots.add(cert).map_err(|e| invalid_arg(&format!("{}: {e}", path.display())))?;
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+
+    // SNI / 证书校验用地址里的主机部分（域名或 IP）
+    let host = args.addr.rsplit_once(':').map_or(args.addr.as_str(), |(h, _)| h);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let name = ServerName::try_from(host.to_string()).map_err(|e| invalid_arg(&format!("{host}: {e}")))?;
+    let stream = TlsConnector::from(Arc::new(config)).connect(name, tcp).await?;
+    Ok(Box::new(stream))
+}
+
+/// `--insecure`：不校验证书链（仍然校验握手签名），只用于自签名测试
+#[derive(Debug)]
+struct NoVerify(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn line_frame(text: String) -> ClientFrame {
+    ClientFrame::Line { text }
+}
+
+/// 房间消息前面加上本地时间和消息 ID（/edit /delete 要用）
+fn render_envelope(env: &Envelope) -> String {
+    match env.stamp {
+        Some(stamp) => {
+            format!("{} [#{}] {}", stamp.ts.with_timezone(&Local).format("%H:%M"), stamp.id, render(&env.frame))
+        }
+        None => render(&env.frame),
+    }
+}
+
+/// 按帧类型渲染成一行输出
+fn render(frame: &ServerFrame) -> String {
+    match frame {
+        ServerFrame::Chat { room, from, text } => format!("#{room} <{from}> {text}"),
+        ServerFrame::Action { room, from, text } => format!("#{room} * {from} {text}"),
+        ServerFrame::Topic { room, by, text } => format!("-- {by} set the topic of #{room}: {text}"),
+        ServerFrame::Edit { room, target, from, text } => format!("#{room} <{from}> (edited #{target}) {text}"),
+        ServerFrame::Delete { room, target, from } => format!("-- {from} deleted message #{target} in #{room}"),
+        ServerFrame::Join { room, nick } => format!("-- {nick} joined #{room}"),
+        ServerFrame::Leave { room, nick } => format!("-- {nick} left #{room}"),
+        ServerFrame::Nick { old, new } => format!("-- {old} is now known as {new}"),
+        ServerFrame::Members { room, members } => format!("** In #{room} ({}): {}", members.len(), describe_members(members)),
+        ServerFrame::Whisper { from, text } => format!("[whisper from {from}] {text}"),
+        ServerFrame::WhisperSent { to, text } => format!("[whisper to {to}] {text}"),
+        ServerFrame::OfflineWhisper { from, text, .. } => format!("[whisper from {from}, while you were away] {text}"),
+        ServerFrame::System { text } => format!("** {text}"),
+        ServerFrame::Error { text } => format!("!! {text}"),
+        ServerFrame::History { frame } => format!("[history] {}", render(frame)),
+        ServerFrame::Ping => "PING".to_string(),
+        // 传文件的帧由 files 处理，一般不会显示
+        ServerFrame::FileOffer { transfer, from, name, size, .. } => {
+            format!("** {from} offers {name} ({size} bytes), transfer {transfer}")
+        }
+        ServerFrame::FileOffered { transfer, to, name, .. } => format!("** Offered {name} to {to}, transfer {transfer}"),
+        ServerFrame::FileAccepted { transfer, by, .. } => format!("** {by} accepted transfer {transfer}"),
+        ServerFrame::FileChunk { transfer, seq, .. } => format!("** Transfer {transfer}: chunk {seq}"),
+        ServerFrame::FileAck { transfer, seq } => format!("** Transfer {transfer}: chunk {seq} received"),
+        ServerFrame::FileDone { transfer, .. } => format!("** Transfer {transfer} finished"),
+        ServerFrame::FileCancelled { transfer, by, reason } => format!("** {by} cancelled transfer {transfer}: {reason}"),
+    }
 }
@@ -0,0 +1,700 @@
+//! 服务端命令和机器人：每个 `/命令` 实现 Command，登记到 Registry；连接循环只管把输入行交给 Registry。
+//!
+//! 加新命令或机器人只要实现对应的 trait 再 `register` / `add_bot`，不用动 handle_conn。
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    pin::Pin,
+    sync::Arc,
+};
+use tokio::sync::mpsc;
+
+use crate::config::Config;
+use crate::protocol::ServerFrame;
+use crate::{
+    LOBBY, NAME_MAX, Outbox, Peer, PresenceCommand, RoomRx, SharedState, broadcast_to_room, chat, deliver_mail, error,
+    find_whisper_target, in_history, list_rooms, login_account, moderate, move_to_room, parse_credentials,
+    parse_history, parse_mod_command, parse_nick, parse_room, parse_search, parse_whisper, presence, queue_mail,
+    register_account, send_history_to_user, send_members, send_query_results, send_topic, set_topic, system, try_change_nick,
+};
+
+/// 命令和机器人的异步返回值（trait 里的 async fn 没法放进 `dyn`，手动装箱）
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// 机器人发言用的地址：不是任何真实连接，所以所有人（包括触发它的人）都能收到
+pub const BOT_ADDR: Peer = Peer { addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)), conn: 0 };
+
+/// 用命令需要的权限，从低到高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    /// 谁都能用
+    User,
+    /// 至少是某个房间的管理员
+    RoomOp,
+    /// 配置里列出的服务器管理员
+    ServerOp,
+}
+
+/// 一个 `/命令`
+pub trait Command: Send + Sync {
+    /// 命令名，带 `/`
+    fn name(&self) -> &'static str;
+    /// 用法，如 `/kick <nick> [reason]`
+    fn usage(&self) -> &'static str;
+    /// 一句话说明，给 /help 用
+    fn about(&self) -> &'static str;
+    /// 需要的权限，默认谁都能用
+    fn privilege(&self) -> Privilege {
+        Privilege::User
+    }
+    /// 执行；arg 是命令名后面的部分（已去掉首尾空白）。Err 作为错误提示发给本人
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>>;
+
+    /// 参数不对时的提示
+    fn usage_error(&self) -> String {
+        format!("Usage: {}", self.usage())
+    }
+}
+
+/// 进程内的机器人：房间里每条普通聊天都会交给它看一眼
+pub trait Bot: Send + Sync {
+    /// 发言时用的名字
+    fn name(&self) -> &'static str;
+    /// 有人在 room 里说了 text；要回话就用 bot.say
+    fn on_chat<'a>(&'a self, bot: &'a BotHandle, room: &'a str, from: &'a str, text: &'a str) -> BoxFuture<'a, ()>;
+}
+
+/// 机器人往房间里说话的途径；可以 clone 进定时任务里
+#[derive(Clone)]
+pub struct BotHandle {
+    name: &'static str,
+    state: SharedState,
+}
+
+impl BotHandle {
+    /// 在房间里说一句（进历史和日志，和普通聊天一样）
+    pub async fn say(&self, room: &str, text: &str) {
+        broadcast_to_room(&self.state, room, BOT_ADDR, self.name, chat(room, self.name, text)).await;
+    }
+}
+
+/// 一个连接的会话状态，命令通过它读写
+pub struct Session {
+    pub peer: Peer,
+    pub state: SharedState,
+    pub config: Arc<Config>,
+    pub name: String,                          // 当前昵称
+    pub room: String,                          // 当前房间（与 State 中的 User::room 保持一致）
+    pub out: Outbox,                           // 只发给本人
+    pub switch: mpsc::UnboundedSender<RoomRx>, // 换房间时把新房间的订阅交给写任务
+    pub mine: VecDeque<u64>,                   // 自己最近发的消息 ID，/edit /delete 只能改这些
+    pub commands: Arc<Registry>,
+}
+
+impl Session {
+    /// 给本人一条提示
+    pub fn reply(&self, text: impl Into<String>) {
+        let _ = self.out.send(system(text));
+    }
+
+    /// 以自己的名义向当前房间广播，返回消息 ID
+    pub async fn broadcast(&self, frame: ServerFrame) -> u64 {
+        broadcast_to_room(&self.state, &self.room, self.peer, &self.name, frame).await
+    }
+
+    /// 发一条自己的消息（聊天、动作），记下 ID 以便之后修改
+    pub async fn post(&mut self, frame: ServerFrame) {
+        let id = self.broadcast(frame).await;
+        if self.mine.len() == self.config.history_cap {
+            self.mine.pop_front(); // 更早的也不在历史缓存里了
+        }
+        self.mine.push_back(id);
+    }
+
+    /// 改昵称并通知房间，再投递新昵称的离线私聊
+    pub async fn rename(&mut self, nick: &str) -> Result<(), String> {
+        let new_name = try_change_nick(&self.state, self.peer, nick.to_string()).await.map_err(|e| e.describe(nick))?;
+        let old = std::mem::replace(&mut self.name, new_name.clone());
+        self.broadcast(ServerFrame::Nick { old, new: new_name }).await;
+        send_members(&self.state, &self.room, &self.out).await; // 自己的改名不会回显，列表靠这个更新
+        deliver_mail(&self.state, &self.name, &self.out).await;
+        Ok(())
+    }
+
+    /// 换到另一个房间：两边都广播，再补发新房间的历史和话题
+    pub async fn switch_room(&mut self, target: String) -> Result<(), String> {
+        if target == self.room {
+            return Err(format!("You are already in #{target}"));
+        }
+        let (new_rx, is_op) = move_to_room(&self.state, self.peer, &target).await;
+        let _ = self.switch.send(new_rx);
+        let old = std::mem::replace(&mut self.room, target);
+        let leave = ServerFrame::Leave { room: old.clone(), nick: self.name.clone() };
+        broadcast_to_room(&self.state, &old, self.peer, &self.name, leave).await;
+        self.broadcast(ServerFrame::Join { room: self.room.clone(), nick: self.name.clone() }).await;
+        let op_note = if is_op { " (you are its operator)" } else { "" };
+        self.reply(format!("Now in #{}{op_note}", self.room));
+        send_history_to_user(&self.state, &self.room, &self.out, None).await;
+        send_topic(&self.state, &self.room, &self.out).await;
+        send_members(&self.state, &self.room, &self.out).await;
+        Ok(())
+    }
+}
+
+/// 所有命令和机器人
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<Arc<dyn Command>>, // 按登记顺序，/help 用
+    by_name: HashMap<&'static str, Arc<dyn Command>>,
+    bots: Vec<Arc<dyn Bot>>,
+}
+
+impl Registry {
+    /// 带全部内置命令
+    pub fn builtin() -> Self {
+        let mut r = Registry::default();
+        r.register(Nick);
+        r.register(Auth { register: true });
+        r.register(Auth { register: false });
+        r.register(Whisper);
+        r.register(Join);
+        r.register(Leave);
+        r.register(Rooms);
+        r.register(Me);
+        r.register(Topic);
+        r.register(Edit { delete: false });
+        r.register(Edit { delete: true });
+        r.register(History);
+        r.register(Search);
+        r.register(Who);
+        r.register(Whois);
+        r.register(Away);
+        r.register(Back);
+        r.register(Moderate::KICK);
+        r.register(Moderate::MUTE);
+        r.register(Moderate::BAN);
+        r.register(Moderate::UNBAN);
+        r.register(Help);
+        r
+    }
+
+    /// 登记一个命令；同名的后登记的覆盖先登记的
+    pub fn register(&mut self, cmd: impl Command + 'static) {
+        let cmd: Arc<dyn Command> = Arc::new(cmd);
+        self.commands.retain(|c| c.name() != cmd.name());
+        self.commands.push(cmd.clone());
+        self.by_name.insert(cmd.name(), cmd);
+    }
+
+    /// 有没有这个命令（`/xxx`）
+    pub fn knows(&self, verb: &str) -> bool {
+        self.by_name.contains_key(verb)
+    }
+
+    /// 加一个机器人
+    pub fn add_bot(&mut self, bot: impl Bot + 'static) {
+        self.bots.push(Arc::new(bot));
+    }
+
+    /// 处理一行输入：`/命令` 查表执行，其余当聊天发出去再交给机器人
+    pub async fn handle_line(&self, s: &mut Session, line: &str) {
+        // `//text` 发一条以 `/` 开头的普通消息
+        let text = match line.strip_prefix("//") {
+            Some(rest) => format!("/{rest}"),
+            None if line.starts_with('/') => {
+                let verb = line.split_whitespace().next().unwrap_or(line);
+                let arg = line[verb.len()..].trim();
+                if let Err(why) = self.run(s, verb, arg).await {
+                    let _ = s.out.send(error(why));
+                }
+                return;
+            }
+            None => line.to_string(),
+        };
+        s.post(chat(&s.room, &s.name, &text)).await;
+        for bot in &self.bots {
+            let handle = BotHandle { name: bot.name(), state: s.state.clone() };
+            bot.on_chat(&handle, &s.room, &s.name, &text).await;
+        }
+    }
+
+    /// 查表、查权限、执行
+    async fn run(&self, s: &mut Session, verb: &str, arg: &str) -> Result<(), String> {
+        let Some(cmd) = self.by_name.get(verb) else {
+            // 不认识的命令：提示一下，不广播
+            return Err(format!(
+                "Unknown command {verb}; type /help for a list, or //text to send text starting with '/'"
+            ));
+        };
+        match cmd.privilege() {
+            Privilege::User => {}
+            need if s.state.lock().await.privilege(s.peer) >= need => {}
+            Privilege::RoomOp => return Err("You are not an operator".to_string()),
+            Privilege::ServerOp => return Err(format!("Only server operators can use {verb}")),
+        }
+        cmd.run(s, arg).await
+    }
+
+    /// 本人能用的命令，一行一条
+    fn help_for(&self, privilege: Privilege) -> Vec<String> {
+        let usable: Vec<_> = self.commands.iter().filter(|c| c.privilege() <= privilege).collect();
+        let width = usable.iter().map(|c| c.usage().len()).max().unwrap_or(0);
+        usable.iter().map(|c| format!("  {:width$}  {}", c.usage(), c.about())).collect()
+    }
+}
+
+// === 内置命令 ===
+
+struct Nick;
+
+impl Command for Nick {
+    fn name(&self) -> &'static str {
+        "/nick"
+    }
+    fn usage(&self) -> &'static str {
+        "/nick <name>"
+    }
+    fn about(&self) -> &'static str {
+        "set or change nickname"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let nick = parse_nick(arg).ok_or_else(|| self.usage_error())?;
+            s.rename(nick).await
+        })
+    }
+}
+
+/// `/register` 和 `/login`，成功后顺便换成账号昵称
+struct Auth {
+    register: bool,
+}
+
+impl Command for Auth {
+    fn name(&self) -> &'static str {
+        if self.register { "/register" } else { "/login" }
+    }
+    fn usage(&self) -> &'static str {
+        if self.register { "/register <name> <password>" } else { "/login <name> <password>" }
+    }
+    fn about(&self) -> &'static str {
+        if self.register { "reserve a nickname" } else { "log in to a registered nickname" }
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let (name, password) = parse_credentials(arg).ok_or_else(|| self.usage_error())?;
+            if self.register {
+                register_account(&s.state, s.peer, name, password).await?;
+                s.reply(format!("Registered and logged in as {name}"));
+            } else {
+                login_account(&s.state, s.peer, name, password).await?;
+                s.reply(format!("Logged in as {name}"));
+            }
+            if s.state.lock().await.is_server_op(s.peer) {
+                s.reply("You are a server operator");
+            }
+            if s.name != name {
+                s.rename(name).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// 私聊；对方不在线就放进信箱
+struct Whisper;
+
+impl Command for Whisper {
+    fn name(&self) -> &'static str {
+        "/w"
+    }
+    fn usage(&self) -> &'static str {
+        "/w <name> <msg>"
+    }
+    fn about(&self) -> &'static str {
+        "whisper (queued if they are offline)"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let (to, msg) = parse_whisper(arg).ok_or_else(|| self.usage_error())?;
+            let Some((target_tx, away)) = find_whisper_target(&s.state, to).await else {
+                let queued = queue_mail(&s.state, &s.name, to, msg).await?;
+                s.reply(queued);
+                return Ok(());
+            };
+            // 对方的写队列满了（不读）就丢了，不能再回显成已发出
+            if !target_tx.send(ServerFrame::Whisper { from: s.name.clone(), text: msg.to_string() }) {
+                return Err(format!("{to} is not reading right now; your whisper was not delivered"));
+            }
+            let _ = s.out.send(ServerFrame::WhisperSent { to: to.to_string(), text: msg.to_string() });
+            // 对方挂了 /away：照样送达，再自动回一句留言
+            if let Some(away) = away {
+                s.reply(format!("{to} is away: {away}"));
+            }
+            Ok(())
+        })
+    }
+}
+
+struct Join;
+
+impl Command for Join {
+    fn name(&self) -> &'static str {
+        "/join"
+    }
+    fn usage(&self) -> &'static str {
+        "/join <room>"
+    }
+    fn about(&self) -> &'static str {
+        "switch to a room (letters, digits, '-' or '_')"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let Some(room) = parse_room(arg) else {
+                return Err(format!("{} (letters, digits, '-' or '_', max {NAME_MAX})", self.usage_error()));
+            };
+            s.switch_room(room).await
+        })
+    }
+}
+
+struct Leave;
+
+impl Command for Leave {
+    fn name(&self) -> &'static str {
+        "/leave"
+    }
+    fn usage(&self) -> &'static str {
+        "/leave"
+    }
+    fn about(&self) -> &'static str {
+        "go back to #lobby"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, _arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(s.switch_room(LOBBY.to_string()))
+    }
+}
+
+struct Rooms;
+
+impl Command for Rooms {
+    fn name(&self) -> &'static str {
+        "/rooms"
+    }
+    fn usage(&self) -> &'static str {
+        "/rooms"
+    }
+    fn about(&self) -> &'static str {
+        "list rooms and member counts"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, _arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            s.reply(format!("Rooms: {}", list_rooms(&s.state).await));
+            Ok(())
+        })
+    }
+}
+
+struct Me;
+
+impl Command for Me {
+    fn name(&self) -> &'static str {
+        "/me"
+    }
+    fn usage(&self) -> &'static str {
+        "/me <action>"
+    }
+    fn about(&self) -> &'static str {
+        "describe what you are doing"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            if arg.is_empty() {
+                return Err(self.usage_error());
+            }
+            s.post(ServerFrame::Action { room: s.room.clone(), from: s.name.clone(), text: arg.to_string() }).await;
+            Ok(())
+        })
+    }
+}
+
+/// `/topic` 查看，`/topic <text>` 修改（房间里的人都能改）
+struct Topic;
+
+impl Command for Topic {
+    fn name(&self) -> &'static str {
+        "/topic"
+    }
+    fn usage(&self) -> &'static str {
+        "/topic [text]"
+    }
+    fn about(&self) -> &'static str {
+        "show or set the room topic"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            if arg.is_empty() {
+                let st = s.state.lock().await;
+                s.reply(match st.topics.get(&s.room) {
+                    Some((text, by)) => format!("Topic for #{}: {text} (set by {by})", s.room),
+                    None => format!("No topic is set for #{}", s.room),
+                });
+                return Ok(());
+            }
+            set_topic(&s.state, &s.room, &s.name, arg).await;
+            s.broadcast(ServerFrame::Topic { room: s.room.clone(), by: s.name.clone(), text: arg.to_string() }).await;
+            Ok(())
+        })
+    }
+}
+
+/// `/edit <id> <text>` 和 `/delete <id>`：只能改自己最近发的、还在历史缓存里的消息
+struct Edit {
+    delete: bool,
+}
+
+impl Command for Edit {
+    fn name(&self) -> &'static str {
+        if self.delete { "/delete" } else { "/edit" }
+    }
+    fn usage(&self) -> &'static str {
+        if self.delete { "/delete <id>" } else { "/edit <id> <text>" }
+    }
+    fn about(&self) -> &'static str {
+        if self.delete { "remove one of your recent messages" } else { "change one of your recent messages" }
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let (id, text) = arg.split_once(char::is_whitespace).map_or((arg, ""), |(a, b)| (a, b.trim()));
+            let id = id.strip_prefix('#').unwrap_or(id).parse::<u64>().map_err(|_| self.usage_error())?;
+            if self.delete != text.is_empty() {
+                return Err(self.usage_error());
+            }
+            if !s.mine.contains(&id) || !in_history(&s.state, &s.room, id).await {
+                return Err(format!("#{id} is not one of your recent messages in #{}", s.room));
+            }
+            let (room, from) = (s.room.clone(), s.name.clone());
+            let frame = if self.delete {
+                s.mine.retain(|&m| m != id);
+                ServerFrame::Delete { room, target: id, from }
+            } else {
+                ServerFrame::Edit { room, target: id, from, text: text.to_string() }
+            };
+            s.broadcast(frame).await;
+            Ok(())
+        })
+    }
+}
+
+struct History;
+
+impl Command for History {
+    fn name(&self) -> &'static str {
+        "/history"
+    }
+    fn usage(&self) -> &'static str {
+        "/history [before <id>] [n]"
+    }
+    fn about(&self) -> &'static str {
+        "older messages in this room, n up to 100"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let query = parse_history(arg, &s.room).ok_or_else(|| self.usage_error())?;
+            send_query_results(&s.config.data_dir, &query, &s.out).await;
+            Ok(())
+        })
+    }
+}
+
+struct Search;
+
+impl Command for Search {
+    fn name(&self) -> &'static str {
+        "/search"
+    }
+    fn usage(&self) -> &'static str {
+        "/search <text> [from:<nick>] [before:<id>]"
+    }
+    fn about(&self) -> &'static str {
+        "search this room's history"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let query = parse_search(arg, &s.room).ok_or_else(|| self.usage_error())?;
+            send_query_results(&s.config.data_dir, &query, &s.out).await;
+            Ok(())
+        })
+    }
+}
+
+struct Who;
+
+impl Command for Who {
+    fn name(&self) -> &'static str {
+        "/who"
+    }
+    fn usage(&self) -> &'static str {
+        "/who [room]"
+    }
+    fn about(&self) -> &'static str {
+        "list who is in this (or another) room"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let room = match arg {
+                "" => s.room.clone(),
+                arg => parse_room(arg).ok_or_else(|| self.usage_error())?,
+            };
+            s.reply(presence(&s.state, s.peer, PresenceCommand::Who { room }).await?);
+            Ok(())
+        })
+    }
+}
+
+struct Whois;
+
+impl Command for Whois {
+    fn name(&self) -> &'static str {
+        "/whois"
+    }
+    fn usage(&self) -> &'static str {
+        "/whois <nick>"
+    }
+    fn about(&self) -> &'static str {
+        "connect time, idle time and away message"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                return Err(self.usage_error());
+            }
+            s.reply(presence(&s.state, s.peer, PresenceCommand::Whois { nick: arg.to_string() }).await?);
+            Ok(())
+        })
+    }
+}
+
+struct Away;
+
+impl Command for Away {
+    fn name(&self) -> &'static str {
+        "/away"
+    }
+    fn usage(&self) -> &'static str {
+        "/away [msg]"
+    }
+    fn about(&self) -> &'static str {
+        "mark yourself away (whispers get an auto-reply)"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let message = if arg.is_empty() { "Away" } else { arg }.to_string();
+            s.reply(presence(&s.state, s.peer, PresenceCommand::Away { message }).await?);
+            Ok(())
+        })
+    }
+}
+
+struct Back;
+
+impl Command for Back {
+    fn name(&self) -> &'static str {
+        "/back"
+    }
+    fn usage(&self) -> &'static str {
+        "/back"
+    }
+    fn about(&self) -> &'static str {
+        "clear your away message"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            if !arg.is_empty() {
+                return Err(self.usage_error());
+            }
+            s.reply(presence(&s.state, s.peer, PresenceCommand::Back).await?);
+            Ok(())
+        })
+    }
+}
+
+/// `/kick` `/mute` `/ban` `/unban`：参数格式一样，执行都在 moderate 里
+struct Moderate {
+    name: &'static str,
+    usage: &'static str,
+    about: &'static str,
+    privilege: Privilege,
+}
+
+impl Moderate {
+    const KICK: Moderate = Moderate {
+        name: "/kick",
+        usage: "/kick <nick> [reason]",
+        about: "disconnect a user",
+        privilege: Privilege::RoomOp,
+    };
+    const MUTE: Moderate = Moderate {
+        name: "/mute",
+        usage: "/mute <nick> [duration, default 10m]",
+        about: "stop a user from talking for a while",
+        privilege: Privilege::RoomOp,
+    };
+    const BAN: Moderate = Moderate {
+        name: "/ban",
+        usage: "/ban <nick|ip> [duration, e.g. 30m, 2h, 7d]",
+        about: "ban an address, for good if no duration",
+        privilege: Privilege::ServerOp,
+    };
+    const UNBAN: Moderate =
+        Moderate { name: "/unban", usage: "/unban <nick|ip>", about: "lift a ban", privilege: Privilege::ServerOp };
+}
+
+impl Command for Moderate {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+    fn about(&self) -> &'static str {
+        self.about
+    }
+    fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let cmd = parse_mod_command(self.name, arg).ok_or_else(|| self.usage_error())??;
+            s.reply(moderate(&s.state, s.peer, &s.name, cmd).await?);
+            Ok(())
+        })
+    }
+}
+
+/// 列出本人能用的命令
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "/help"
+    }
+    fn usage(&self) -> &'static str {
+        "/help"
+    }
+    fn about(&self) -> &'static str {
+        "this list; //text sends text starting with '/'"
+    }
+    fn run<'a>(&'a self, s: &'a mut Session, _arg: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let privilege = s.state.lock().await.privilege(s.peer);
+            s.reply("Commands:");
+            for line in s.commands.help_for(privilege) {
+                s.reply(line);
+            }
+            Ok(())
+        })
+    }
+}
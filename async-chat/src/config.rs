@@ -0,0 +1,309 @@
+//! 服务器配置：默认值 <- TOML 配置文件 <- 命令行覆盖，最后统一校验
+
+use std::{fmt, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+use serde::Deserialize;
+
+/// 所有可调参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: SocketAddr,          // 监听地址
+    pub data_dir: PathBuf,         // 聊天日志和账号所在目录
+    pub history_cap: usize,        // 每个房间的历史缓存条数
+    pub idle_timeout_secs: u64,    // 多久无输入断开
+    pub heartbeat_secs: u64,       // PING 间隔
+    pub max_missed_heartbeats: u32, // 连续多少个 PING 没回 PONG 就断开
+    pub broadcast_capacity: usize, // 每个房间广播通道容量
+    pub max_connections: usize,    // 同时在线连接上限
+    pub shutdown_grace_secs: u64,  // 关停时最多等连接收尾多久
+    pub outbound_queue: usize,     // 每个连接的私聊写队列长度
+    pub slow_consumer_policy: SlowConsumerPolicy, // 客户端读得太慢怎么办
+    pub slow_consumer_strikes: u32, // 连续几个心跳周期都在丢消息算“太慢”
+    pub max_line_len: usize,       // 单行输入最大字节数
+    pub rate_limit_per_sec: u32,   // 每秒允许的输入行数（令牌补充速度）
+    pub rate_limit_burst: u32,     // 允许的突发行数（令牌桶容量）
+    pub flood_warnings: u32,       // 刷屏先警告几次再禁言
+    pub flood_mute_secs: u64,      // 禁言多久；禁言后再刷屏就断开
+    pub operators: Vec<String>,    // 服务器管理员（注册账号名，登录后生效）
+    pub mailbox_size: usize,       // 每人最多存几封离线私聊
+    pub mailbox_seen_secs: u64,    // 未注册的昵称离线多久以内还能收离线私聊
+    pub tls_cert: Option<PathBuf>, // 证书链 PEM，和 tls_key 一起给才启用 TLS
+    pub tls_key: Option<PathBuf>,  // 私钥 PEM
+    pub ws_bind: Option<SocketAddr>, // WebSocket 网关监听地址，不给就不开
+    pub irc_bind: Option<SocketAddr>, // IRC 网关监听地址，不给就不开
+    pub max_file_size: u64,        // /send 传文件的大小上限（字节），0 表示不允许传文件
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: SocketAddr::from(([127, 0, 0, 1], 7000)),
+            data_dir: PathBuf::from("data"),
+            history_cap: 50,
+            idle_timeout_secs: 300, // 5 分钟
+            heartbeat_secs: 5,
+            max_missed_heartbeats: 3,
+            broadcast_capacity: 200,
+            max_connections: 1024,
+            shutdown_grace_secs: 5,
+            outbound_queue: 256,
+            slow_consumer_policy: SlowConsumerPolicy::Disconnect,
+            slow_consumer_strikes: 3,
+            max_line_len: 4096,
+            rate_limit_per_sec: 5,
+            rate_limit_burst: 10,
+            flood_warnings: 2,
+            flood_mute_secs: 30,
+            operators: Vec::new(),
+            mailbox_size: 20,
+            mailbox_seen_secs: 24 * 60 * 60, // 1 天
+            tls_cert: None,
+            tls_key: None,
+            ws_bind: None,
+            irc_bind: None,
+            max_file_size: 10 * 1024 * 1024, // 10 MiB
+        }
+    }
+}
+
+/// 慢客户端（队列满、广播落后）的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowConsumerPolicy {
+    /// 提示丢了多少条，连续 slow_consumer_strikes 个心跳周期都在丢就断开
+    Disconnect,
+    /// 只提示，不断开
+    Notify,
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+            "notify" => Ok(SlowConsumerPolicy::Notify),
+            _ => Err("expected `disconnect` or `notify`".to_string()),
+        }
+    }
+}
+
+/// 配置出错：尽量指出是哪个设置
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 配置文件读不了
+    Read { path: PathBuf, err: std::io::Error },
+    /// 配置文件不是合法 TOML，或字段名/类型不对
+    Parse { path: PathBuf, err: toml::de::Error },
+    /// 某个设置的值不合法
+    Invalid { setting: &'static str, reason: String },
+    /// 命令行参数本身有问题
+    Arg(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, err } => write!(f, "cannot read config {}: {err}", path.display()),
+            ConfigError::Parse { path, err } => write!(f, "invalid config {}: {err}", path.display()),
+            ConfigError::Invalid { setting, reason } => write!(f, "invalid setting `{setting}`: {reason}"),
+            ConfigError::Arg(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// 命令行参数名 -> 配置项名
+const OPTIONS: &[(&str, &str)] = &[
+    ("--bind", "bind"),
+    ("--data-dir", "data_dir"),
+    ("--history-cap", "history_cap"),
+    ("--idle-timeout", "idle_timeout_secs"),
+    ("--heartbeat", "heartbeat_secs"),
+    ("--max-missed-heartbeats", "max_missed_heartbeats"),
+    ("--broadcast-capacity", "broadcast_capacity"),
+    ("--max-connections", "max_connections"),
+    ("--shutdown-grace", "shutdown_grace_secs"),
+    ("--outbound-queue", "outbound_queue"),
+    ("--slow-consumer-policy", "slow_consumer_policy"),
+    ("--slow-consumer-strikes", "slow_consumer_strikes"),
+    ("--max-line-len", "max_line_len"),
+    ("--rate-limit", "rate_limit_per_sec"),
+    ("--rate-burst", "rate_limit_burst"),
+    ("--flood-warnings", "flood_warnings"),
+    ("--flood-mute", "flood_mute_secs"),
+    ("--operator", "operators"), // 可以给多次，追加到配置文件的列表里
+    ("--mailbox-size", "mailbox_size"),
+    ("--mailbox-seen", "mailbox_seen_secs"),
+    ("--tls-cert", "tls_cert"),
+    ("--tls-key", "tls_key"),
+    ("--ws-bind", "ws_bind"),
+    ("--irc-bind", "irc_bind"),
+    ("--max-file-size", "max_file_size"),
+];
+
+impl Config {
+    /// 解析命令行：`--config <toml>` 先加载文件，其余 `--xxx <value>` 覆盖对应项
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut file = None;
+        let mut overrides = Vec::new();
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| ConfigError::Arg(format!("{arg} needs a value")))?;
+            if arg == "--config" {
+                file = Some(PathBuf::from(value));
+                continue;
+            }
+            let Some(&(_, setting)) = OPTIONS.iter().find(|(flag, _)| *flag == arg) else {
+                return Err(ConfigError::Arg(format!("unknown argument: {arg}")));
+            };
+            overrides.push((setting, value));
+        }
+
+        let mut config = match file {
+            Some(path) => Self::load(path)?,
+            None => Config::default(),
+        };
+        for (setting, value) in overrides {
+            config.set(setting, &value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 读取 TOML 配置文件，缺省的字段用默认值
+    pub fn load(path: PathBuf) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(&path).map_err(|err| ConfigError::Read { path: path.clone(), err })?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse { path, err })
+    }
+
+    /// 用字符串设置单个配置项（命令行覆盖用）
+    fn set(&mut self, setting: &'static str, value: &str) -> Result<(), ConfigError> {
+        fn parse<T: FromStr>(setting: &'static str, value: &str) -> Result<T, ConfigError>
+        where
+            T::Err: fmt::Display,
+        {
+            value.parse().map_err(|e| ConfigError::Invalid { setting, reason: format!("'{value}': {e}") })
+        }
+
+        match setting {
+            "bind" => self.bind = parse(setting, value)?,
+            "data_dir" => self.data_dir = PathBuf::from(value),
+            "history_cap" => self.history_cap = parse(setting, value)?,
+            "idle_timeout_secs" => self.idle_timeout_secs = parse(setting, value)?,
+            "heartbeat_secs" => self.heartbeat_secs = parse(setting, value)?,
+            "max_missed_heartbeats" => self.max_missed_heartbeats = parse(setting, value)?,
+            "broadcast_capacity" => self.broadcast_capacity = parse(setting, value)?,
+            "max_connections" => self.max_connections = parse(setting, value)?,
+            "shutdown_grace_secs" => self.shutdown_grace_secs = parse(setting, value)?,
+            "outbound_queue" => self.outbound_queue = parse(setting, value)?,
+            "slow_consumer_policy" => self.slow_consumer_policy = parse(setting, value)?,
+            "slow_consumer_strikes" => self.slow_consumer_strikes = parse(setting, value)?,
+            "max_line_len" => self.max_line_len = parse(setting, value)?,
+            "rate_limit_per_sec" => self.rate_limit_per_sec = parse(setting, value)?,
+            "rate_limit_burst" => self.rate_limit_burst = parse(setting, value)?,
+            "flood_warnings" => self.flood_warnings = parse(setting, value)?,
+            "flood_mute_secs" => self.flood_mute_secs = parse(setting, value)?,
+            "operators" => self.operators.push(value.to_string()),
+            "mailbox_size" => self.mailbox_size = parse(setting, value)?,
+            "mailbox_seen_secs" => self.mailbox_seen_secs = parse(setting, value)?,
+            "tls_cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key" => self.tls_key = Some(PathBuf::from(value)),
+            "ws_bind" => self.ws_bind = Some(parse(setting, value)?),
+            "irc_bind" => self.irc_bind = Some(parse(setting, value)?),
+            "max_file_size" => self.max_file_size = parse(setting, value)?,
+            _ => unreachable!("setting listed in OPTIONS"),
+        }
+        Ok(())
+    }
+
+    /// 检查取值范围和组合
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |setting, reason: &str| Err(ConfigError::Invalid { setting, reason: reason.to_string() });
+
+        if self.history_cap == 0 {
+            return invalid("history_cap", "must be at least 1");
+        }
+        if self.idle_timeout_secs == 0 {
+            return invalid("idle_timeout_secs", "must be at least 1");
+        }
+        if self.heartbeat_secs == 0 {
+            return invalid("heartbeat_secs", "must be at least 1");
+        }
+        if self.heartbeat_secs >= self.idle_timeout_secs {
+            return invalid("heartbeat_secs", "must be shorter than idle_timeout_secs");
+        }
+        if self.max_missed_heartbeats == 0 {
+            return invalid("max_missed_heartbeats", "must be at least 1");
+        }
+        if self.broadcast_capacity == 0 {
+            return invalid("broadcast_capacity", "must be at least 1");
+        }
+        if self.max_connections == 0 {
+            return invalid("max_connections", "must be at least 1");
+        }
+        if self.mailbox_size == 0 {
+            return invalid("mailbox_size", "must be at least 1");
+        }
+        // 历史回放和离线私聊都走私聊队列，上线时要一次放得下
+        if self.outbound_queue < self.history_cap + self.mailbox_size {
+            return invalid("outbound_queue", "must be at least history_cap + mailbox_size");
+        }
+        if self.slow_consumer_strikes == 0 {
+            return invalid("slow_consumer_strikes", "must be at least 1");
+        }
+        // 至少要放得下 `/proto json` 和一条正常的 JSON 帧
+        if self.max_line_len < 64 {
+            return invalid("max_line_len", "must be at least 64");
+        }
+        if self.rate_limit_per_sec == 0 {
+            return invalid("rate_limit_per_sec", "must be at least 1");
+        }
+        if self.rate_limit_burst == 0 {
+            return invalid("rate_limit_burst", "must be at least 1");
+        }
+        if self.flood_mute_secs == 0 {
+            return invalid("flood_mute_secs", "must be at least 1");
+        }
+        if self.operators.iter().any(|op| op.is_empty()) {
+            return invalid("operators", "names must not be empty");
+        }
+        if self.data_dir.as_os_str().is_empty() {
+            return invalid("data_dir", "must not be empty");
+        }
+        // 几个监听地址不能撞（端口 0 是让系统挑，不算）
+        let mut listeners = vec![self.bind];
+        for (setting, addr) in [("ws_bind", self.ws_bind), ("irc_bind", self.irc_bind)] {
+            let Some(addr) = addr else { continue };
+            if addr.port() != 0 && listeners.contains(&addr) {
+                return invalid(setting, "must differ from the other listen addresses");
+            }
+            listeners.push(addr);
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => invalid("tls_key", "required when tls_cert is set"),
+            (None, Some(_)) => invalid("tls_cert", "required when tls_key is set"),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_secs)
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_secs)
+    }
+
+    pub fn flood_mute(&self) -> Duration {
+        Duration::from_secs(self.flood_mute_secs)
+    }
+
+    pub fn mailbox_seen(&self) -> Duration {
+        Duration::from_secs(self.mailbox_seen_secs)
+    }
+}
@@ -0,0 +1,429 @@
+//! 客户端这边的传文件：`/send` 先把大小报给对方，对方 `/accept` 后按块发过去，边发边算 SHA-256，
+//! 发完随 FileDone 一起给对方。收到的先写成 `.part`，核对大小和校验和再改成正式文件名，放在下载目录里。
+//!
+//! 服务器只转发不存，连接断了传输就作废，重连后要重新 `/send`
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use async_chat::protocol::{file_name, ClientFrame, ServerFrame};
+
+use crate::{note, Screen};
+
+type Out = mpsc::UnboundedSender<ClientFrame>;
+
+/// 这个连接上所有的传输
+pub struct Files {
+    dir: PathBuf,                                // 下载目录
+    offering: HashMap<(String, String), PathBuf>, // (对方, 文件名) -> 路径：报出去了，还没拿到编号
+    sending: HashMap<u64, Sending>,
+    offers: BTreeMap<u64, Offer>, // 别人报过来、还没接的
+    receiving: HashMap<u64, Receiving>,
+}
+
+/// 自己发出的一个文件
+struct Sending {
+    to: String,
+    name: String,
+    path: PathBuf,
+    size: u64,
+    chunk: u64,
+    acked: watch::Sender<u64>, // 对方确认了几块，发送任务按它控制窗口
+    task: Option<JoinHandle<()>>,
+    progress: Progress,
+}
+
+/// 别人报过来的文件
+struct Offer {
+    from: String,
+    name: String,
+    size: u64,
+}
+
+/// 正在收的文件
+struct Receiving {
+    offer: Offer,
+    part: PathBuf, // 收完校验通过才改名
+    file: File,
+    hasher: Sha256,
+    received: u64,
+    next_seq: u64,
+    progress: Progress,
+}
+
+impl Files {
+    pub fn new(dir: PathBuf) -> Self {
+        Files {
+            dir,
+            offering: HashMap::new(),
+            sending: HashMap::new(),
+            offers: BTreeMap::new(),
+            receiving: HashMap::new(),
+        }
+    }
+
+    /// 用户输入的传文件命令；不是的话返回 false，照常发给服务器
+    pub async fn command(&mut self, line: &str, out: &Out, screen: &Screen) -> bool {
+        let line = line.trim();
+        let (verb, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        match verb {
+            "/send" => match arg.split_once(char::is_whitespace) {
+                Some((to, path)) => self.offer(to, Path::new(path.trim()), out, screen).await,
+                None => note(screen, "Usage: /send <nick> <path>"),
+            },
+            "/accept" => match pick(arg, self.offers.keys().copied().collect()) {
+                Ok(transfer) => self.accept(transfer, out, screen).await,
+                Err(why) => note(screen, format!("** {why} (offers: {})", list(self.offers.keys()))),
+            },
+            "/reject" => match pick(arg, self.offers.keys().copied().collect()) {
+                Ok(transfer) => {
+                    self.offers.remove(&transfer);
+                    cancel(out, transfer, "rejected");
+                }
+                Err(why) => note(screen, format!("** {why} (offers: {})", list(self.offers.keys()))),
+            },
+            // 本地的收尾等服务器回 FileCancelled 再做，两边一个流程
+            "/cancel" => {
+                let active: Vec<u64> = self.sending.keys().chain(self.receiving.keys()).copied().collect();
+                match pick(arg, active.clone()) {
+                    Ok(transfer) => cancel(out, transfer, "cancelled"),
+                    Err(why) => note(screen, format!("** {why} (transfers: {})", list(active.iter()))),
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// `/send`：只看一眼大小就报给服务器（超过上限它马上就拒），等它回 FileOffered 分配编号。
+    /// 校验和等发的时候边读边算，不在这里把整个文件读一遍，免得卡住收帧、回不了心跳
+    async fn offer(&mut self, to: &str, path: &Path, out: &Out, screen: &Screen) {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()).and_then(file_name) else {
+            note(screen, format!("** Can't send {}: not a file name", path.display()));
+            return;
+        };
+        let size = match fs::metadata(path).await {
+            Ok(meta) if meta.is_file() => meta.len(),
+            Ok(_) => {
+                note(screen, format!("** Can't send {}: not a regular file", path.display()));
+                return;
+            }
+            Err(e) => {
+                note(screen, format!("** Can't send {}: {e}", path.display()));
+                return;
+            }
+        };
+        self.offering.insert((to.to_string(), name.to_string()), path.to_path_buf());
+        let frame = ClientFrame::FileOffer { to: to.to_string(), name: name.to_string(), size };
+        let _ = out.send(frame);
+    }
+
+    /// `/accept`：先建好 `.part` 文件再告诉对方可以发了
+    async fn accept(&mut self, transfer: u64, out: &Out, screen: &Screen) {
+        let Some(offer) = self.offers.remove(&transfer) else { return };
+        let part = self.dir.join(format!("{}.{transfer}.part", offer.name));
+        let file = match fs::create_dir_all(&self.dir).await {
+            Ok(()) => File::create(&part).await,
+            Err(e) => Err(e),
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                note(screen, format!("** Can't save to {}: {e}", part.display()));
+                cancel(out, transfer, "receiver can't save the file");
+                return;
+            }
+        };
+        note(screen, format!("** Receiving {} from {} ({})...", offer.name, offer.from, human(offer.size)));
+        let _ = out.send(ClientFrame::FileAccept { transfer });
+        let progress = Progress::default();
+        let receiving = Receiving { offer, part, file, hasher: Sha256::new(), received: 0, next_seq: 0, progress };
+        self.receiving.insert(transfer, receiving);
+    }
+
+    /// 服务器发来的传文件帧
+    pub async fn on_frame(&mut self, frame: ServerFrame, out: &Out, screen: &Screen) {
+        match frame {
+            ServerFrame::FileOffer { transfer, from, name, size } => {
+                let Some(name) = file_name(&name).map(str::to_string) else {
+                    cancel(out, transfer, "bad file name");
+                    return;
+                };
+                let hint = format!("/accept {transfer} or /reject {transfer}");
+                note(screen, format!("** {from} wants to send you {name} ({}): {hint}", human(size)));
+                self.offers.insert(transfer, Offer { from, name, size });
+            }
+            ServerFrame::FileOffered { transfer, to, name, size } => {
+                let Some(path) = self.offering.remove(&(to.clone(), name.clone())) else { return };
+                note(screen, format!("** Offered {name} ({}) to {to}, waiting for them to accept", human(size)));
+                let (acked, _) = watch::channel(0);
+                let progress = Progress::default();
+                let sending = Sending { to, name, path, size, chunk: 0, acked, task: None, progress };
+                self.sending.insert(transfer, sending);
+            }
+            ServerFrame::FileAccepted { transfer, by, chunk, window } => {
+                let Some(sending) = self.sending.get_mut(&transfer) else { return };
+                note(screen, format!("** {by} accepted {}, sending...", sending.name));
+                if sending.size == 0 {
+                    let _ = out.send(ClientFrame::FileDone { transfer, sha256: hex(Sha256::new()) });
+                    return;
+                }
+                sending.chunk = chunk as u64;
+                let stream = Stream {
+                    transfer,
+                    path: sending.path.clone(),
+                    size: sending.size,
+                    chunk,
+                    window: window as u64,
+                    acked: sending.acked.subscribe(),
+                };
+                sending.task = Some(tokio::spawn(stream.run(out.clone())));
+            }
+            ServerFrame::FileAck { transfer, seq } => {
+                let Some(sending) = self.sending.get_mut(&transfer) else { return };
+                sending.acked.send_replace(seq + 1);
+                let done = ((seq + 1) * sending.chunk).min(sending.size);
+                if let Some(pct) = sending.progress.advance(done, sending.size).filter(|&pct| pct < 100) {
+                    note(screen, format!("** Sending {} to {}: {pct}%", sending.name, sending.to));
+                }
+            }
+            ServerFrame::FileChunk { transfer, seq, data } => {
+                let Some(receiving) = self.receiving.get_mut(&transfer) else { return };
+                if let Err(why) = receiving.write(seq, &data).await {
+                    cancel(out, transfer, &why);
+                    return;
+                }
+                let _ = out.send(ClientFrame::FileAck { transfer, seq });
+                let Receiving { offer, received, progress, .. } = receiving;
+                if let Some(pct) = progress.advance(*received, offer.size).filter(|&pct| pct < 100) {
+                    note(screen, format!("** Receiving {} from {}: {pct}%", offer.name, offer.from));
+                }
+            }
+            // 收的一方：发完了，校验通过就回一个 FileDone 告诉对方存好了；发的一方：对方存好了
+            ServerFrame::FileDone { transfer, sha256 } => {
+                if let Some(sending) = self.sending.remove(&transfer) {
+                    note(screen, format!("** Sent {} to {}", sending.name, sending.to));
+                    return;
+                }
+                let Some(receiving) = self.receiving.remove(&transfer) else { return };
+                let (name, size) = (receiving.offer.name.clone(), receiving.offer.size);
+                match receiving.finish(&sha256, &self.dir).await {
+                    Ok(path) => {
+                        let _ = out.send(ClientFrame::FileDone { transfer, sha256: String::new() });
+                        note(screen, format!("** Saved {name} ({}) to {}", human(size), path.display()));
+                    }
+                    Err(why) => {
+                        cancel(out, transfer, &why);
+                        note(screen, format!("!! Discarded {name}: {why}"));
+                    }
+                }
+            }
+            ServerFrame::FileCancelled { transfer, by, reason } => {
+                let name = if let Some(sending) = self.sending.remove(&transfer) {
+                    if let Some(task) = sending.task {
+                        task.abort();
+                    }
+                    sending.name
+                } else if let Some(receiving) = self.receiving.remove(&transfer) {
+                    drop(receiving.file);
+                    let _ = fs::remove_file(&receiving.part).await;
+                    receiving.offer.name
+                } else if let Some(offer) = self.offers.remove(&transfer) {
+                    offer.name
+                } else {
+                    return;
+                };
+                note(screen, format!("** Transfer {transfer} ({name}) cancelled by {by}: {reason}"));
+            }
+            _ => {}
+        }
+    }
+
+    /// 连接断了：服务器那边的传输都作废了，停掉发送、删掉收了一半的文件
+    pub async fn abandon(&mut self, screen: &Screen) {
+        for (_, sending) in self.sending.drain() {
+            if let Some(task) = sending.task {
+                task.abort();
+                note(screen, format!("** Sending {} to {} was interrupted", sending.name, sending.to));
+            }
+        }
+        for (_, receiving) in self.receiving.drain() {
+            drop(receiving.file);
+            let _ = fs::remove_file(&receiving.part).await;
+            note(screen, format!("** Receiving {} from {} was interrupted", receiving.offer.name, receiving.offer.from));
+        }
+        self.offering.clear();
+        self.offers.clear();
+    }
+}
+
+impl Receiving {
+    /// 写一块：顺序、大小不对就算坏了
+    async fn write(&mut self, seq: u64, data: &str) -> Result<(), String> {
+        if seq != self.next_seq {
+            return Err(format!("chunk {seq} out of order"));
+        }
+        let bytes = BASE64.decode(data).map_err(|_| format!("chunk {seq} is not base64"))?;
+        if self.received + bytes.len() as u64 > self.offer.size {
+            return Err("more data than offered".to_string());
+        }
+        self.file.write_all(&bytes).await.map_err(|e| format!("receiver can't write the file: {e}"))?;
+        self.hasher.update(&bytes);
+        self.received += bytes.len() as u64;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// 发完了：核对大小和校验和，通过就改成不和已有文件重名的正式名字
+    async fn finish(mut self, sha256: &str, dir: &Path) -> Result<PathBuf, String> {
+        let checked = if self.received != self.offer.size {
+            Err(format!("got {} of {} bytes", self.received, self.offer.size))
+        } else if hex(self.hasher) != sha256 {
+            Err("checksum mismatch".to_string())
+        } else {
+            self.file.flush().await.map_err(|e| e.to_string())
+        };
+        drop(self.file);
+        if let Err(why) = checked {
+            let _ = fs::remove_file(&self.part).await;
+            return Err(why);
+        }
+        let path = free_path(dir, &self.offer.name);
+        fs::rename(&self.part, &path).await.map_err(|e| format!("can't rename {}: {e}", self.part.display()))?;
+        Ok(path)
+    }
+}
+
+/// 发送任务：按块读文件发出去，最多领先确认 window 块；顺便算校验和，发完附在 FileDone 里
+struct Stream {
+    transfer: u64,
+    path: PathBuf,
+    size: u64,
+    chunk: usize,
+    window: u64,
+    acked: watch::Receiver<u64>,
+}
+
+impl Stream {
+    async fn run(mut self, out: Out) {
+        let transfer = self.transfer;
+        let frame = match self.send_all(&out).await {
+            Ok(Some(sha256)) => ClientFrame::FileDone { transfer, sha256 },
+            Ok(None) => return, // 取消了
+            Err(reason) => ClientFrame::FileCancel { transfer, reason },
+        };
+        let _ = out.send(frame);
+    }
+
+    async fn send_all(&mut self, out: &Out) -> Result<Option<String>, String> {
+        let mut file = File::open(&self.path).await.map_err(|e| format!("sender can't read the file: {e}"))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; self.chunk];
+        let (mut seq, mut sent) = (0, 0);
+        while sent < self.size {
+            let window = self.window;
+            if self.acked.wait_for(|&acked| seq < acked + window).await.is_err() {
+                return Ok(None);
+            }
+            let n = (self.size - sent).min(self.chunk as u64) as usize;
+            // 报价之后文件变短了也算读失败；变长了只发报过的那些
+            file.read_exact(&mut buf[..n]).await.map_err(|e| format!("sender can't read the file: {e}"))?;
+            hasher.update(&buf[..n]);
+            let frame = ClientFrame::FileChunk { transfer: self.transfer, seq, data: BASE64.encode(&buf[..n]) };
+            if out.send(frame).is_err() {
+                return Ok(None);
+            }
+            seq += 1;
+            sent += n as u64;
+        }
+        Ok(Some(hex(hasher)))
+    }
+}
+
+fn cancel(out: &Out, transfer: u64, reason: &str) {
+    let _ = out.send(ClientFrame::FileCancel { transfer, reason: reason.to_string() });
+}
+
+/// 进度提示：每过 10% 说一次
+#[derive(Default)]
+struct Progress {
+    shown: u64, // 已经提示到第几个 10%
+}
+
+impl Progress {
+    fn advance(&mut self, done: u64, total: u64) -> Option<u64> {
+        let pct = (done * 100).checked_div(total).unwrap_or(100);
+        (pct / 10 > self.shown).then(|| {
+            self.shown = pct / 10;
+            pct
+        })
+    }
+}
+
+/// SHA-256 写成 hex
+fn hex(hasher: Sha256) -> String {
+    format!("{:x}", hasher.finalize())
+}
+
+/// 下载目录里不和已有文件重名的路径：`a.txt`、`a (1).txt`、`a (2).txt`……
+fn free_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+    let mut path = dir.join(name);
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{stem} ({n}){ext}"));
+        n += 1;
+    }
+    path
+}
+
+/// 没给编号时：只有一个就是它
+fn pick(arg: &str, candidates: Vec<u64>) -> Result<u64, String> {
+    match (arg.trim_start_matches('#'), candidates.as_slice()) {
+        ("", []) => Err("Nothing to choose from".to_string()),
+        ("", [only]) => Ok(*only),
+        ("", _) => Err("Which one? Give the transfer number".to_string()),
+        (id, _) => match id.parse() {
+            Ok(id) if candidates.contains(&id) => Ok(id),
+            _ => Err(format!("No transfer {id}")),
+        },
+    }
+}
+
+fn list<'a>(ids: impl Iterator<Item = &'a u64>) -> String {
+    let ids: Vec<String> = ids.map(u64::to_string).collect();
+    if ids.is_empty() { "none".to_string() } else { ids.join(", ") }
+}
+
+/// 字节数写成 `512 B` / `3.4 KB` / `1.2 MB`
+fn human(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// 服务器发来的这一帧是不是传文件的，是的话交给 Files 而不是直接显示
+pub fn is_file_frame(frame: &ServerFrame) -> bool {
+    matches!(
+        frame,
+        ServerFrame::FileOffer { .. }
+            | ServerFrame::FileOffered { .. }
+            | ServerFrame::FileAccepted { .. }
+            | ServerFrame::FileChunk { .. }
+            | ServerFrame::FileAck { .. }
+            | ServerFrame::FileDone { .. }
+            | ServerFrame::FileCancelled { .. }
+    )
+}
@@ -0,0 +1,517 @@
+//! IRC 网关：普通 IRC 客户端从另一个端口进来，和原生客户端在同一批房间里聊。
+//!
+//! 跟 WebSocket 网关一样，每条连接在进程内接一根 duplex 管道交给 [`ChatHandle::serve`]，用 JSON 模式和
+//! handle_conn 说话：IRC 命令翻译成聊天输入，服务器的帧翻译回 IRC 消息；名单、话题、昵称检查直接查 State。
+//! 只支持常用的一小部分（NICK USER JOIN PART PRIVMSG NOTICE PING PONG QUIT NAMES TOPIC），
+//! 聊天服务器自己的命令（login、away、whois……）可以用客户端的 `/quote` 原样发过来。
+//! 聊天室里一次只在一个房间，所以 JOIN 别的频道就是换房间，客户端会先收到离开原频道的 PART
+
+use std::net::SocketAddr;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, WriteHalf};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+
+use crate::limits::{BoundedLines, RawLine};
+use crate::protocol::{ClientFrame, Envelope, Member, ServerFrame, PROTO_JSON};
+use crate::{is_valid_name, room_members, to_text, ChatHandle, NickError, Peer, NAME_MAX};
+
+/// 报给客户端的服务器名，也当所有人的主机名
+const SERVER: &str = "async-chat";
+/// NICK + USER 最多等多久
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(60);
+/// 网关和 handle_conn 之间的管道缓冲
+const PIPE_BUFFER: usize = 64 * 1024;
+/// 一条 353 里最多放几个名字，免得超过 IRC 的 512 字节行长
+const NAMES_PER_LINE: usize = 20;
+
+/// 注册完发给客户端的 MOTD
+const MOTD: &[&str] = &[
+    "You are in one room at a time: JOIN another channel to switch, PART to go back to #lobby.",
+    "Other chat commands go through /quote, e.g. /quote login <name> <password> or /quote away <message>.",
+];
+
+type Lines = BoundedLines<BufReader<OwnedReadHalf>>;
+
+impl ChatHandle {
+    /// 在监听器上接 IRC 客户端，直到关停。连接数上限、封禁和 TCP 共用
+    pub async fn run_irc(&self, listener: TcpListener) -> io::Result<()> {
+        let mut stop = self.inner.shutdown.subscribe();
+        loop {
+            let (socket, peer) = tokio::select! {
+                res = listener.accept() => res?,
+                _ = stop.wait_for(|stop| *stop) => return Ok(()),
+            };
+            let handle = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle.irc_session(socket, peer).await {
+                    eprintln!("! IRC {peer}: {e}");
+                }
+            });
+        }
+    }
+
+    /// 一条 IRC 连接：先注册，再在客户端和 handle_conn 之间来回翻译
+    async fn irc_session(&self, socket: TcpStream, addr: SocketAddr) -> io::Result<()> {
+        // 注册时查昵称、之后查话题权限用的都是这个身份，和交给 handle_conn 的一致
+        let peer = Peer::new(addr);
+        let (reader, writer) = socket.into_split();
+        let mut lines = BoundedLines::new(BufReader::new(reader), self.inner.config.max_line_len);
+        let mut irc = Irc { out: writer, nick: "*".to_string(), room: None, switching: true, backlog: Vec::new() };
+
+        match timeout(REGISTER_TIMEOUT, self.irc_register(&mut irc, &mut lines, peer)).await {
+            Ok(Ok(true)) => {}
+            Ok(res) => return res.map(drop), // 没注册完就走了
+            Err(_) => return irc.send("ERROR :Registration timed out").await,
+        }
+
+        let (ours, theirs) = io::duplex(PIPE_BUFFER);
+        self.serve_as(theirs, peer).await;
+        let (reader, writer) = io::split(ours);
+        let mut frames = BufReader::new(reader).lines();
+        let mut chat = Pipe::open(writer).await;
+        chat.line(format!("/nick {}", irc.nick)).await;
+        irc.welcome().await?;
+
+        loop {
+            tokio::select! {
+                // 换房间还没落定时先不读客户端：IRC 客户端常常 JOIN 完紧接着就往新频道发东西
+                line = lines.next_line(), if !irc.switching => match line? {
+                    Some(RawLine::Text(line)) => {
+                        let Some(msg) = Message::parse(&line) else { continue };
+                        if !self.irc_command(&mut irc, &mut chat, peer, msg).await? {
+                            break;
+                        }
+                    }
+                    Some(RawLine::TooLong) => irc.numeric("417", ":Input line was too long").await?,
+                    None => break,
+                },
+                line = frames.next_line() => match line? {
+                    Some(line) => self.irc_frame(&mut irc, &mut chat, peer, &line).await?,
+                    None => {
+                        // 服务器那边结束了（被踢、超时、关停……），提示已经作为 NOTICE 发过了
+                        irc.send("ERROR :Closing link").await?;
+                        break;
+                    }
+                },
+            }
+        }
+        // 丢掉管道，handle_conn 读到 EOF 自己收尾
+        Ok(())
+    }
+
+    /// IRC 注册：NICK 和 USER 都到了、昵称也能用才算完。没注册完客户端就走了返回 false
+    async fn irc_register(&self, irc: &mut Irc, lines: &mut Lines, peer: Peer) -> io::Result<bool> {
+        let (mut nick, mut user) = (None, false);
+        loop {
+            if let (Some(nick), true) = (&nick, user) {
+                irc.nick = String::clone(nick);
+                return Ok(true);
+            }
+            let line = match lines.next_line().await? {
+                Some(RawLine::Text(line)) => line,
+                Some(RawLine::TooLong) => continue,
+                None => return Ok(false),
+            };
+            let Some(msg) = Message::parse(&line) else { continue };
+            match msg.command.as_str() {
+                // 不支持任何扩展能力，回个空表客户端就会 CAP END
+                "CAP" if msg.arg(0) == Some("LS") => irc.send(format!(":{SERVER} CAP * LS :")).await?,
+                "CAP" | "PASS" => {}
+                "NICK" => match msg.arg(0) {
+                    Some(wanted) if self.irc_nick_ok(irc, peer, wanted).await? => nick = Some(wanted.to_string()),
+                    Some(_) => {}
+                    None => irc.numeric("431", ":No nickname given").await?,
+                },
+                "USER" if msg.params.len() >= 4 => user = true,
+                "USER" => irc.numeric("461", "USER :Not enough parameters").await?,
+                "PING" => irc.pong(msg.arg(0)).await?,
+                "QUIT" => return Ok(false),
+                _ => irc.numeric("451", ":You have not registered").await?,
+            }
+        }
+    }
+
+    /// 昵称能不能用；不能用就回 432/433 并返回 false。真正改名还是 handle_conn 做，这里只是提前按 IRC 的方式报错
+    async fn irc_nick_ok(&self, irc: &mut Irc, peer: Peer, wanted: &str) -> io::Result<bool> {
+        if !is_valid_name(wanted) {
+            irc.numeric("432", &format!("{wanted} :Erroneous nickname")).await?;
+            return Ok(false);
+        }
+        let checked = self.inner.state.lock().await.check_nick(peer, wanted);
+        match checked {
+            Ok(()) => return Ok(true),
+            Err(NickError::Taken) => irc.numeric("433", &format!("{wanted} :Nickname is already in use")).await?,
+            Err(NickError::Registered) => {
+                let reason = format!("Nickname is registered; use another nick, then /quote login {wanted} <password>");
+                irc.numeric("433", &format!("{wanted} :{reason}")).await?;
+            }
+        }
+        Ok(false)
+    }
+
+    /// 注册之后客户端的一条命令；返回 false 表示客户端要走了
+    async fn irc_command(&self, irc: &mut Irc, chat: &mut Pipe, peer: Peer, msg: Message) -> io::Result<bool> {
+        match msg.command.as_str() {
+            "PING" => irc.pong(msg.arg(0)).await?,
+            "PONG" => chat.frame(&ClientFrame::Pong).await,
+            "NICK" => match msg.arg(0) {
+                None => irc.numeric("431", ":No nickname given").await?,
+                Some(wanted) if wanted == irc.nick => {}
+                // 改名成功后服务器会发新的成员列表，到时候再告诉客户端
+                Some(wanted) => {
+                    if self.irc_nick_ok(irc, peer, wanted).await? {
+                        chat.line(format!("/nick {wanted}")).await;
+                    }
+                }
+            },
+            "USER" | "PASS" => irc.numeric("462", ":You may not reregister").await?,
+            "CAP" => {}
+            "JOIN" => match msg.arg(0) {
+                None => irc.numeric("461", "JOIN :Not enough parameters").await?,
+                // 给了好几个就挨个换过去，最后停在最后一个
+                Some(list) => {
+                    for room in list.split(',').filter_map(channel) {
+                        if irc.room.as_deref() != Some(room.as_str()) {
+                            chat.line(format!("/join {room}")).await;
+                            irc.switching = true;
+                        }
+                    }
+                }
+            },
+            "PART" => match msg.arg(0) {
+                None => irc.numeric("461", "PART :Not enough parameters").await?,
+                // 只能离开当前所在的房间，回大厅
+                Some(list) => {
+                    for name in list.split(',') {
+                        if channel(name).is_some() && channel(name) == irc.room {
+                            chat.line("/leave").await;
+                            irc.switching = true;
+                        } else {
+                            irc.numeric("442", &format!("{name} :You're not on that channel")).await?;
+                        }
+                    }
+                }
+            },
+            "PRIVMSG" | "NOTICE" => match (msg.arg(0), msg.arg(1)) {
+                (None, _) => irc.numeric("411", &format!(":No recipient given ({})", msg.command)).await?,
+                (_, None | Some("")) => irc.numeric("412", ":No text to send").await?,
+                (Some(target), Some(text)) => self.irc_privmsg(irc, chat, target, text).await?,
+            },
+            "NAMES" => {
+                let rooms: Vec<String> = match msg.arg(0) {
+                    Some(list) => list.split(',').filter_map(channel).collect(),
+                    None => irc.room.iter().cloned().collect(),
+                };
+                for room in rooms {
+                    let members = room_members(&*self.inner.state.lock().await, &room);
+                    irc.names(&room, &members).await?;
+                }
+            }
+            "TOPIC" => match (msg.arg(0).and_then(channel), msg.arg(1)) {
+                (None, _) => irc.numeric("461", "TOPIC :Not enough parameters").await?,
+                (Some(room), None | Some("")) => {
+                    let topic = self.inner.state.lock().await.topics.get(&room).cloned();
+                    match topic {
+                        Some((text, _)) => irc.numeric("332", &format!("#{room} :{text}")).await?,
+                        None => irc.numeric("331", &format!("#{room} :No topic is set")).await?,
+                    }
+                }
+                (Some(room), Some(_)) if irc.room.as_deref() != Some(room.as_str()) => {
+                    irc.numeric("442", &format!("#{room} :You're not on that channel")).await?;
+                }
+                (Some(room), Some(text)) => {
+                    chat.line(format!("/topic {text}")).await;
+                    // 自己改的话题服务器不回显，IRC 客户端却要看到 TOPIC 才更新
+                    let me = irc.nick.clone();
+                    irc.from(&me, &format!("TOPIC #{room} :{text}")).await?;
+                }
+            },
+            "QUIT" => {
+                irc.send("ERROR :Closing link").await?;
+                return Ok(false);
+            }
+            verb => {
+                // 聊天服务器自己的命令原样转过去，回复以 NOTICE 回来
+                let command = format!("/{}", verb.to_ascii_lowercase());
+                if self.inner.commands.knows(&command) {
+                    let line = std::iter::once(command).chain(msg.params).collect::<Vec<_>>().join(" ");
+                    chat.line(line).await;
+                } else {
+                    irc.numeric("421", &format!("{verb} :Unknown command")).await?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// PRIVMSG/NOTICE：发到频道是群聊，发给人是私聊
+    async fn irc_privmsg(&self, irc: &mut Irc, chat: &mut Pipe, target: &str, text: &str) -> io::Result<()> {
+        // CTCP 只认 ACTION（/me），VERSION 之类的不理
+        let (action, text) = match text.strip_prefix('\x01') {
+            Some(ctcp) => match ctcp.trim_end_matches('\x01').strip_prefix("ACTION ") {
+                Some(action) => (true, action),
+                None => return Ok(()),
+            },
+            None => (false, text),
+        };
+        let line = match channel(target) {
+            Some(room) if irc.room.as_deref() != Some(room.as_str()) => {
+                return irc.numeric("404", &format!("{target} :Cannot send to channel (you are not in it)")).await;
+            }
+            Some(_) if action => format!("/me {text}"),
+            Some(_) if text.starts_with('/') => format!("/{text}"), // 以 `/` 开头的普通消息要写成 `//`
+            Some(_) => text.to_string(),
+            None if action => format!("/w {target} * {} {text}", irc.nick),
+            None => format!("/w {target} {text}"),
+        };
+        chat.line(line).await;
+        Ok(())
+    }
+
+    /// 把服务器发来的一行（JSON 帧）翻译给 IRC 客户端
+    async fn irc_frame(&self, irc: &mut Irc, chat: &mut Pipe, peer: Peer, line: &str) -> io::Result<()> {
+        let env = match serde_json::from_str::<Envelope>(line) {
+            Ok(env) => env,
+            // 不是 JSON：连接被拒（满员、封禁、关停中）时的那一行
+            Err(_) if !line.starts_with('{') => {
+                return irc.send(format!("ERROR :{}", line.trim_start_matches("** "))).await;
+            }
+            // 是 JSON 但解不出来（两边协议对不上）：记日志跳过，别让客户端以为出了大错
+            Err(e) => {
+                eprintln!("! IRC {peer}: skipping a frame we can't decode ({e}): {line}");
+                return Ok(());
+            }
+        };
+        let me = irc.nick.clone();
+        match env.frame {
+            ServerFrame::Chat { room, from, text } => irc.privmsg(&from, &format!("#{room}"), &text).await,
+            ServerFrame::Action { room, from, text } => {
+                irc.privmsg(&from, &format!("#{room}"), &format!("\x01ACTION {text}\x01")).await
+            }
+            ServerFrame::Topic { room, by, text } => irc.from(&by, &format!("TOPIC #{room} :{text}")).await,
+            ServerFrame::Join { room, nick } => irc.from(&nick, &format!("JOIN #{room}")).await,
+            ServerFrame::Leave { room, nick } => irc.from(&nick, &format!("PART #{room}")).await,
+            ServerFrame::Nick { old, new } => irc.from(&old, &format!("NICK :{new}")).await,
+            ServerFrame::Members { room, members } => self.irc_members(irc, peer, room, members).await,
+            ServerFrame::Whisper { from, text } => irc.privmsg(&from, &me, &text).await,
+            ServerFrame::OfflineWhisper { from, text, .. } => {
+                irc.privmsg(&from, &me, &format!("[while you were away] {text}")).await
+            }
+            ServerFrame::WhisperSent { .. } => Ok(()), // IRC 客户端自己会显示发出去的
+            // 历史回放先于成员列表到，这时客户端还没进频道：攒着，发完 JOIN 再补
+            ServerFrame::History { frame } => {
+                let time = env.stamp.map(|s| s.ts.format(" %H:%M").to_string()).unwrap_or_default();
+                let text = format!("[history{time}] {}", to_text(&frame));
+                match room_of(&frame) {
+                    Some(room) if irc.room.as_deref() == Some(room) => irc.notice(&format!("#{room}"), &text).await,
+                    Some(room) => {
+                        irc.backlog.push((room.to_string(), text));
+                        Ok(())
+                    }
+                    None => irc.notice(&me, &text).await,
+                }
+            }
+            ref frame @ (ServerFrame::Edit { ref room, .. } | ServerFrame::Delete { ref room, .. }) => {
+                irc.notice(&format!("#{room}"), &to_text(frame)).await
+            }
+            ServerFrame::System { text } => irc.notice(&me, &text).await,
+            // 换房间失败（房间名不对、已经在大厅……）也是一条 Error
+            ServerFrame::Error { text } => {
+                irc.switching = false;
+                irc.notice(&me, &text).await
+            }
+            ServerFrame::Ping => irc.send(format!("PING :{SERVER}")).await,
+            // IRC 没有对应的传文件办法（DCC 要直连），直接替用户拒掉，告诉一声
+            ServerFrame::FileOffer { transfer, from, name, .. } => {
+                let reason = "IRC clients can't receive files".to_string();
+                chat.frame(&ClientFrame::FileCancel { transfer, reason }).await;
+                irc.notice(&me, &format!("{from} tried to send you {name}; IRC clients can't receive files")).await
+            }
+            // 自己发不了文件，其余几帧不会来
+            ServerFrame::FileOffered { .. }
+            | ServerFrame::FileAccepted { .. }
+            | ServerFrame::FileChunk { .. }
+            | ServerFrame::FileAck { .. }
+            | ServerFrame::FileDone { .. }
+            | ServerFrame::FileCancelled { .. } => Ok(()),
+        }
+    }
+
+    /// 进了房间或者自己改了名（服务器这两种时候会发成员列表）：对上客户端眼里的昵称和频道
+    async fn irc_members(&self, irc: &mut Irc, peer: Peer, room: String, members: Vec<Member>) -> io::Result<()> {
+        // 自己现在叫什么以 State 为准：/nick、/login 成功后都会走到这里
+        let actual = self.inner.state.lock().await.by_addr.get(&peer).map(|u| u.name.clone());
+        if let Some(actual) = actual.filter(|name| *name != irc.nick) {
+            let old = std::mem::replace(&mut irc.nick, actual);
+            let new = irc.nick.clone();
+            irc.from(&old, &format!("NICK :{new}")).await?;
+        }
+        if irc.room.as_deref() == Some(room.as_str()) {
+            return Ok(());
+        }
+
+        let me = irc.nick.clone();
+        irc.switching = false;
+        if let Some(old) = irc.room.replace(room.clone()) {
+            irc.from(&me, &format!("PART #{old}")).await?;
+        }
+        irc.from(&me, &format!("JOIN #{room}")).await?;
+        let topic = self.inner.state.lock().await.topics.get(&room).cloned();
+        if let Some((text, _)) = topic {
+            irc.numeric("332", &format!("#{room} :{text}")).await?;
+        }
+        irc.names(&room, &members).await?;
+        for (r, text) in std::mem::take(&mut irc.backlog) {
+            if r == room {
+                irc.notice(&format!("#{room}"), &text).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 写给 IRC 客户端的一头，外加客户端眼里的状态
+struct Irc {
+    out: OwnedWriteHalf,
+    nick: String,                   // 客户端眼里自己叫什么（注册完之前是 `*`）
+    room: Option<String>,           // 客户端眼里自己在哪个频道（不带 `#`）
+    switching: bool,                // 在等换房间的结果（成员列表或者报错）；刚连上时在等进大厅
+    backlog: Vec<(String, String)>, // 还没 JOIN 的房间的历史回放：(房间, 文本)
+}
+
+impl Irc {
+    /// 所有输出都走这里：话题、昵称、原因都是用户给的，夹着 CR/LF/NUL 就能多写出一行协议
+    async fn send(&mut self, line: impl AsRef<str>) -> io::Result<()> {
+        let line: String =
+            line.as_ref().chars().map(|c| if matches!(c, '\r' | '\n' | '\0') { ' ' } else { c }).collect();
+        self.out.write_all(format!("{line}\r\n").as_bytes()).await
+    }
+
+    /// 服务器的数字回复
+    async fn numeric(&mut self, code: &str, rest: &str) -> io::Result<()> {
+        let line = format!(":{SERVER} {code} {} {rest}", self.nick);
+        self.send(line).await
+    }
+
+    /// 以某人的身份发的消息
+    async fn from(&mut self, nick: &str, rest: &str) -> io::Result<()> {
+        self.send(format!(":{nick}!{nick}@{SERVER} {rest}")).await
+    }
+
+    /// IRC 一行只能一条，多行的拆开发
+    async fn privmsg(&mut self, from: &str, target: &str, text: &str) -> io::Result<()> {
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            self.from(from, &format!("PRIVMSG {target} :{line}")).await?;
+        }
+        Ok(())
+    }
+
+    async fn notice(&mut self, target: &str, text: &str) -> io::Result<()> {
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            self.send(format!(":{SERVER} NOTICE {target} :{line}")).await?;
+        }
+        Ok(())
+    }
+
+    async fn pong(&mut self, token: Option<&str>) -> io::Result<()> {
+        self.send(format!(":{SERVER} PONG {SERVER} :{}", token.unwrap_or(SERVER))).await
+    }
+
+    /// 353 + 366，房间管理员前面带 `@`
+    async fn names(&mut self, room: &str, members: &[Member]) -> io::Result<()> {
+        let names: Vec<String> =
+            members.iter().map(|m| if m.op { format!("@{}", m.nick) } else { m.nick.clone() }).collect();
+        for chunk in names.chunks(NAMES_PER_LINE) {
+            self.numeric("353", &format!("= #{room} :{}", chunk.join(" "))).await?;
+        }
+        self.numeric("366", &format!("#{room} :End of /NAMES list")).await
+    }
+
+    /// 注册完的欢迎词：001-005 和 MOTD
+    async fn welcome(&mut self) -> io::Result<()> {
+        let nick = self.nick.clone();
+        self.numeric("001", &format!(":Welcome to the {SERVER} IRC gateway, {nick}")).await?;
+        self.numeric("002", &format!(":Your host is {SERVER}, running version {}", env!("CARGO_PKG_VERSION"))).await?;
+        self.numeric("004", &format!("{SERVER} {} o o", env!("CARGO_PKG_VERSION"))).await?;
+        let isupport = format!("CHANTYPES=# PREFIX=(o)@ NICKLEN={NAME_MAX} CHANNELLEN={}", NAME_MAX + 1);
+        self.numeric("005", &format!("{isupport} :are supported by this server")).await?;
+        self.numeric("375", &format!(":- {SERVER} Message of the day -")).await?;
+        for line in MOTD {
+            self.numeric("372", &format!(":- {line}")).await?;
+        }
+        self.numeric("376", ":End of /MOTD command").await
+    }
+}
+
+/// 写给 handle_conn 的一头（JSON 模式）。写失败说明连接已经在收尾，读那头马上会 EOF，这里不管
+struct Pipe(WriteHalf<DuplexStream>);
+
+impl Pipe {
+    async fn open(writer: WriteHalf<DuplexStream>) -> Self {
+        let mut pipe = Pipe(writer);
+        let _ = pipe.0.write_all(format!("{PROTO_JSON}\n").as_bytes()).await;
+        pipe
+    }
+
+    async fn line(&mut self, text: impl Into<String>) {
+        self.frame(&ClientFrame::Line { text: text.into() }).await
+    }
+
+    async fn frame(&mut self, frame: &ClientFrame) {
+        let mut line = serde_json::to_string(frame).expect("ClientFrame always serializes");
+        line.push('\n');
+        let _ = self.0.write_all(line.as_bytes()).await;
+    }
+}
+
+/// 一条 IRC 消息；tags 和前缀用不到，解析时扔掉
+struct Message {
+    command: String, // 统一大写
+    params: Vec<String>,
+}
+
+impl Message {
+    fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_start();
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ')?.1.trim_start();
+        }
+        if rest.starts_with(':') {
+            rest = rest.split_once(' ')?.1.trim_start();
+        }
+        // ` :` 之后是最后一个参数，可以带空格
+        let (middle, trailing) = match rest.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut words = middle.split(' ').filter(|w| !w.is_empty());
+        let command = words.next()?.to_ascii_uppercase();
+        let mut params: Vec<String> = words.map(String::from).collect();
+        params.extend(trailing.map(String::from));
+        Some(Message { command, params })
+    }
+
+    fn arg(&self, i: usize) -> Option<&str> {
+        self.params.get(i).map(String::as_str)
+    }
+}
+
+/// `#name` -> 房间名；房间名在服务器里都是小写
+fn channel(name: &str) -> Option<String> {
+    name.strip_prefix('#').filter(|n| !n.is_empty()).map(str::to_ascii_lowercase)
+}
+
+/// 房间消息属于哪个房间
+fn room_of(frame: &ServerFrame) -> Option<&str> {
+    match frame {
+        ServerFrame::Chat { room, .. }
+        | ServerFrame::Action { room, .. }
+        | ServerFrame::Topic { room, .. }
+        | ServerFrame::Edit { room, .. }
+        | ServerFrame::Delete { room, .. }
+        | ServerFrame::Join { room, .. }
+        | ServerFrame::Leave { room, .. } => Some(room),
+        _ => None,
+    }
+}
@@ -0,0 +1,1721 @@
+//! 聊天服务器库：`server` 程序只是它外面薄薄的一层，也可以嵌进自己的 tokio 程序里、在进程内测试。
+//!
+//! ```no_run
+//! use async_chat::{ChatServer, Config};
+//! use tokio::net::TcpListener;
+//!
+//! # async fn demo() -> std::io::Result<()> {
+//! let server = ChatServer::new(Config::default()).data_dir("/tmp/chat").start().await?;
+//! let listener = TcpListener::bind("127.0.0.1:0").await?;
+//! let handle = server.clone();
+//! tokio::spawn(async move { handle.run(listener).await });
+//!
+//! server.announce("Maintenance in 5 minutes").await;
+//! for user in server.users().await {
+//!     println!("{} in #{}", user.nick, user.room);
+//! }
+//! server.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
+};
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{interval, timeout, timeout_at, Duration, Instant};
+use tokio_rustls::TlsAcceptor;
+
+mod accounts;
+mod bots;
+mod chat_log;
+mod commands;
+pub mod config;
+mod irc;
+mod limits;
+mod mailbox;
+mod moderation;
+pub mod protocol;
+mod tls;
+mod transfer;
+mod websocket;
+pub use config::Config;
+use accounts::{Accounts, Credential};
+use chat_log::{LogRecord, Query, Replay};
+use commands::{Privilege, Registry, Session};
+use config::SlowConsumerPolicy;
+use limits::{BoundedLines, FloodGuard, RawLine, Verdict};
+use mailbox::Mailbox;
+use moderation::{format_duration, parse_duration, Bans};
+use transfer::Transfers;
+use chrono::Utc;
+use protocol::{describe_members, file_name, ClientFrame, Envelope, Member, ServerFrame, Stamp, PROTO_JSON};
+
+/// === 固定参数（可调的在 config.rs）===
+const LOBBY: &str = "lobby";              // 默认房间，新连接都先进这里
+const NAME_MAX: usize = 32;               // 房间名 / 注册昵称最大长度
+const PASSWORD_MIN: usize = 6;            // 注册密码最短长度
+const MUTE_DEFAULT: Duration = Duration::from_secs(10 * 60); // /mute 不给时长时禁言多久
+const HISTORY_PAGE: usize = 20;           // /history、/search 默认每页几条
+const HISTORY_PAGE_MAX: usize = 100;      // /history <n> 最多几条
+const FILE_CHUNK_MAX: usize = 48 * 1024;  // 文件每块最多几字节（还受 max_line_len 限制）
+const FILE_WINDOW_MAX: usize = 16;        // 发送方最多领先几块没确认
+const FILE_OFFERS_MAX: usize = 5;         // 每人同时进行的传输数
+
+/// 一条连接：对方地址加进程内唯一的编号。网关转进来的连接可能和 TCP 连接是同一个 ip:port，
+/// 光拿地址当 State 的键会互相覆盖；封禁、日志还是看地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Peer {
+    addr: SocketAddr,
+    conn: u64,
+}
+
+impl Peer {
+    /// 编号从 1 开始，0 留给机器人（commands::BOT_ADDR）
+    fn new(addr: SocketAddr) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Peer { addr, conn: NEXT.fetch_add(1, Ordering::Relaxed) }
+    }
+
+    fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.addr.fmt(f)
+    }
+}
+
+/// 房间广播：写任务订阅它并写回到客户端
+type RoomTx = broadcast::Sender<(Peer, Envelope)>;
+type RoomRx = broadcast::Receiver<(Peer, Envelope)>;
+
+/// 一个聊天室：广播通道 + 成员（历史放在 State::history，房间空了也不丢）
+struct Room {
+    tx: RoomTx,
+    members: HashSet<Peer>,
+    ops: HashSet<Peer>, // 房间管理员：第一个进房间的人，离开房间就失去
+}
+
+impl Room {
+    fn new(capacity: usize) -> Self {
+        let (tx, _rx) = broadcast::channel(capacity);
+        Room { tx, members: HashSet::new(), ops: HashSet::new() }
+    }
+}
+
+/// 在线用户信息（供私聊用）
+struct User {
+    name: String,
+    room: String,                      // 当前所在房间
+    account: Option<String>,           // 已登录的注册账号，游客为 None
+    tx: Outbox,                        // 该用户的私聊写队列
+    control: mpsc::UnboundedSender<Control>, // 管理命令（踢出、禁言）发给该连接
+    connected: Instant,                // 连上的时间
+    last_active: Instant,              // 最后一次输入（不算 PONG），/whois 的空闲时间
+    away: Option<String>,              // /away 留言，None 为在线
+    json: bool,                        // JSON 模式的客户端才能收文件
+}
+
+/// 管理员对某个连接的操作，由该连接自己的任务执行
+enum Control {
+    /// 断开，附带给本人的说明
+    Kick(String),
+    /// 禁言到某个时刻
+    Mute { until: Instant, notice: String },
+}
+
+/// 每个连接的私聊写队列：有界，满了就丢掉并计数，由写任务告诉客户端丢了几条
+#[derive(Clone)]
+struct Outbox {
+    tx: mpsc::Sender<Envelope>,
+    dropped: Arc<AtomicU32>,
+}
+
+impl Outbox {
+    fn new(capacity: usize) -> (Self, mpsc::Receiver<Envelope>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (Outbox { tx, dropped: Arc::new(AtomicU32::new(0)) }, rx)
+    }
+
+    /// 不阻塞发送者；返回是否进了队列
+    fn send(&self, frame: impl Into<Envelope>) -> bool {
+        match self.tx.try_send(frame.into()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// 共享在线状态（按地址/昵称检索 + 房间表 + 历史缓存）
+struct State {
+    by_addr: HashMap<Peer, User>,
+    by_name: HashMap<String, Peer>,
+    rooms: HashMap<String, Room>,
+    history: HashMap<String, VecDeque<Envelope>>, // 房间名 -> 最近 N 条历史
+    last_id: u64,                                 // 最后分配的消息 ID，所有房间共用一个序列
+    topics: HashMap<String, (String, String)>,    // 房间 -> (话题, 设置者)
+    log_tx: mpsc::UnboundedSender<LogRecord>,   // 日志写任务
+    accounts: Accounts,                          // 注册账号，对应昵称被保留
+    bans: Bans,                                  // 封禁的 IP
+    mailbox: Mailbox,                            // 离线私聊
+    transfers: Transfers,                        // 进行中的文件传输
+    seen: HashMap<String, Instant>,              // 下线（或改名）的昵称 -> 最后在线时间
+    config: Arc<Config>,
+}
+
+impl State {
+    /// 创建状态，大厅始终存在；历史和话题由日志回放得到
+    fn new(
+        replay: Replay,
+        log_tx: mpsc::UnboundedSender<LogRecord>,
+        accounts: Accounts,
+        bans: Bans,
+        mailbox: Mailbox,
+        config: Arc<Config>,
+    ) -> Self {
+        let mut rooms = HashMap::new();
+        rooms.insert(LOBBY.to_string(), Room::new(config.broadcast_capacity));
+        State {
+            by_addr: HashMap::new(),
+            by_name: HashMap::new(),
+            rooms,
+            history: replay.history,
+            last_id: replay.last_id,
+            topics: replay.topics,
+            log_tx,
+            accounts,
+            bans,
+            mailbox,
+            transfers: Transfers::default(),
+            seen: HashMap::new(),
+            config,
+        }
+    }
+
+    /// 取房间，不存在就按配置的容量新建
+    fn room_mut(&mut self, room: &str) -> &mut Room {
+        let capacity = self.config.broadcast_capacity;
+        self.rooms.entry(room.to_string()).or_insert_with(|| Room::new(capacity))
+    }
+
+    /// 昵称能否被 peer 使用：没被别人占用，且不是别人的注册昵称
+    fn check_nick(&self, peer: Peer, name: &str) -> Result<(), NickError> {
+        if self.by_name.get(name).is_some_and(|&p| p != peer) {
+            return Err(NickError::Taken);
+        }
+        let owner = self.by_addr.get(&peer).and_then(|u| u.account.as_deref());
+        if self.accounts.is_registered(name) && owner != Some(name) {
+            return Err(NickError::Registered);
+        }
+        Ok(())
+    }
+
+    /// 记下某个昵称刚下线；顺手清掉太久以前的
+    fn mark_seen(&mut self, name: &str) {
+        let window = self.config.mailbox_seen();
+        self.seen.retain(|_, at| at.elapsed() < window);
+        self.seen.insert(name.to_string(), Instant::now());
+    }
+
+    /// 能不能给不在线的 name 留言：注册过的昵称，或者最近在线过
+    fn can_receive_mail(&self, name: &str) -> bool {
+        self.accounts.is_registered(name)
+            || self.seen.get(name).is_some_and(|at| at.elapsed() < self.config.mailbox_seen())
+    }
+
+    /// 服务器管理员：以配置里列出的账号登录的用户
+    fn is_server_op(&self, peer: Peer) -> bool {
+        let account = self.by_addr.get(&peer).and_then(|u| u.account.as_deref());
+        account.is_some_and(|a| self.config.operators.iter().any(|op| op == a))
+    }
+
+    /// 某个连接的权限：服务器管理员 > 任一房间的管理员 > 普通用户
+    fn privilege(&self, peer: Peer) -> Privilege {
+        if self.is_server_op(peer) {
+            Privilege::ServerOp
+        } else if self.rooms.values().any(|r| r.ops.contains(&peer)) {
+            Privilege::RoomOp
+        } else {
+            Privilege::User
+        }
+    }
+
+    /// actor 能否管 target：服务器管理员谁都能管；房间管理员只能管同房间里的普通用户
+    fn can_moderate(&self, actor: Peer, target: Peer) -> bool {
+        if actor == target || self.is_server_op(target) {
+            return false;
+        }
+        if self.is_server_op(actor) {
+            return true;
+        }
+        let Some(room) = self.by_addr.get(&target).map(|u| u.room.as_str()) else { return false };
+        self.rooms.get(room).is_some_and(|r| r.ops.contains(&actor) && !r.ops.contains(&target))
+    }
+}
+
+/// 设置昵称失败的原因
+enum NickError {
+    Taken,      // 有人在用
+    Registered, // 已被注册，需要先 /login
+}
+
+impl NickError {
+    fn describe(&self, nick: &str) -> String {
+        match self {
+            NickError::Taken => format!("Nick '{nick}' is taken"),
+            NickError::Registered => format!("Nick '{nick}' is registered; use /login {nick} <password>"),
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+// === 对外接口 ===
+
+/// 服务器构建器：`ChatServer::new(config)` 调好参数，`start()` 得到 [`ChatHandle`]
+pub struct ChatServer {
+    config: Config,
+    bots: bool,
+}
+
+impl ChatServer {
+    pub fn new(config: Config) -> Self {
+        ChatServer { config, bots: true }
+    }
+
+    /// 聊天日志、账号、封禁、离线私聊放在哪个目录
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.data_dir = dir.into();
+        self
+    }
+
+    /// 带不带自带的机器人（掷骰子、/remind），默认带
+    pub fn bots(mut self, on: bool) -> Self {
+        self.bots = on;
+        self
+    }
+
+    /// 校验配置、加载数据目录、启动日志写任务。这时还没在监听，交给 [`ChatHandle::run`]
+    pub async fn start(self) -> io::Result<ChatHandle> {
+        let config = self.config;
+        config.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let data_dir = &config.data_dir;
+
+        // 先回放历史，再打开日志继续追加
+        let replay = chat_log::load_recent(data_dir, config.history_cap).await?;
+        let log = chat_log::ChatLog::open(data_dir).await?;
+        let (log_tx, log_task) = chat_log::spawn_writer(log);
+        let accounts = Accounts::load(data_dir).await?;
+        let bans = Bans::load(data_dir).await?;
+        let mailbox = Mailbox::load(data_dir).await?;
+
+        // 给了证书和私钥就走 TLS，否则明文 TCP（两者是否成对已在配置校验里检查）
+        let tls = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::load_acceptor(cert, key)?),
+            _ => None,
+        };
+
+        // 连接数上限：每个连接持有一个许可，断开时归还
+        let slots = Arc::new(Semaphore::new(config.max_connections));
+        let config = Arc::new(config);
+        let state = Arc::new(Mutex::new(State::new(replay, log_tx, accounts, bans, mailbox, config.clone())));
+        // 内置命令，再加上自带的机器人
+        let mut commands = Registry::builtin();
+        if self.bots {
+            commands.add_bot(bots::Dice);
+            commands.register(bots::Remind::default());
+        }
+
+        let (shutdown, _) = watch::channel(false);
+        Ok(ChatHandle {
+            inner: Arc::new(Inner {
+                state,
+                config,
+                commands: Arc::new(commands),
+                tls,
+                slots,
+                stopping: AtomicBool::new(false),
+                shutdown,
+                conns: std::sync::Mutex::new(JoinSet::new()),
+                log_task: Mutex::new(Some(log_task)),
+            }),
+        })
+    }
+}
+
+/// 跑起来的服务器。可以随便 clone，在别的任务里接连接、发通知、查用户、关停
+#[derive(Clone)]
+pub struct ChatHandle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: SharedState,
+    config: Arc<Config>,
+    commands: Arc<Registry>,
+    tls: Option<TlsAcceptor>,
+    slots: Arc<Semaphore>,
+    stopping: AtomicBool, // 开始关停了，不再接新连接
+    // 关停信号：watch 通道通知所有连接和 run；JoinSet 跟踪连接任务，关停时等它们结束
+    shutdown: watch::Sender<bool>,
+    conns: std::sync::Mutex<JoinSet<()>>,
+    log_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// 一个在线用户，[`ChatHandle::users`] 返回
+#[derive(Debug, Clone)]
+pub struct UserInfo {
+    pub nick: String,
+    pub addr: SocketAddr,
+    pub room: String,
+    pub account: Option<String>, // 登录的注册账号
+    pub operator: bool,          // 服务器管理员
+    pub away: Option<String>,
+    pub connected: Duration, // 连上多久了
+    pub idle: Duration,      // 多久没输入了
+}
+
+impl ChatHandle {
+    /// 在监听器上接受连接，直到关停；配置了 TLS 就先握手。可以同时跑在好几个监听器上
+    pub async fn run(&self, listener: TcpListener) -> io::Result<()> {
+        let mut stop = self.inner.shutdown.subscribe();
+        loop {
+            let (socket, addr) = tokio::select! {
+                res = listener.accept() => res?,
+                _ = stop.wait_for(|stop| *stop) => return Ok(()),
+            };
+            let peer = Peer::new(addr);
+            match (self.admit(peer).await, self.inner.tls.clone()) {
+                (Ok(permit), Some(acceptor)) => self.spawn(peer, permit, async move { acceptor.accept(socket).await }),
+                (Ok(permit), None) => self.spawn(peer, permit, async move { Ok(socket) }),
+                // TLS 连接直接关（还没握手，写了对方也看不懂）
+                (Err(msg), None) => refuse(socket, msg),
+                (Err(_), Some(_)) => {}
+            }
+        }
+    }
+
+    /// 接管一条已经建立好的连接（不做 TLS）：进程内的 duplex 流、别的协议转过来的连接……
+    /// addr 用来检查封禁和显示，和别的连接重了也没关系。连接在后台跑，这里马上返回
+    pub async fn serve<S>(&self, stream: S, addr: SocketAddr)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        self.serve_as(stream, Peer::new(addr)).await;
+    }
+
+    /// 同上，连接的身份由调用方先定好（IRC 网关注册时就要拿它查昵称）
+    pub(crate) async fn serve_as<S>(&self, stream: S, peer: Peer)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        match self.admit(peer).await {
+            Ok(permit) => self.spawn(peer, permit, async move { Ok(stream) }),
+            Err(msg) => refuse(stream, msg),
+        }
+    }
+
+    /// 给所有在线用户发一条系统消息
+    pub async fn announce(&self, text: impl Into<String>) {
+        notify_all(&self.inner.state, system(text)).await;
+    }
+
+    /// 只发给某个房间里的人；房间里没人返回 false
+    pub async fn announce_to(&self, room: &str, text: impl Into<String>) -> bool {
+        let st = self.inner.state.lock().await;
+        let Some(r) = st.rooms.get(room.trim_start_matches('#')).filter(|r| !r.members.is_empty()) else {
+            return false;
+        };
+        let frame = system(text);
+        for user in r.members.iter().filter_map(|p| st.by_addr.get(p)) {
+            user.tx.send(frame.clone());
+        }
+        true
+    }
+
+    /// 在线用户，按昵称排序
+    pub async fn users(&self) -> Vec<UserInfo> {
+        let st = self.inner.state.lock().await;
+        let mut users: Vec<UserInfo> = st
+            .by_addr
+            .iter()
+            .map(|(&peer, u)| UserInfo {
+                nick: u.name.clone(),
+                addr: peer.addr,
+                room: u.room.clone(),
+                account: u.account.clone(),
+                operator: st.is_server_op(peer),
+                away: u.away.clone(),
+                connected: u.connected.elapsed(),
+                idle: u.last_active.elapsed(),
+            })
+            .collect();
+        users.sort_by(|a, b| a.nick.cmp(&b.nick));
+        users
+    }
+
+    /// 某个房间里的人，和客户端进房间时收到的 Members 一样
+    pub async fn members(&self, room: &str) -> Vec<Member> {
+        room_members(&*self.inner.state.lock().await, room.trim_start_matches('#'))
+    }
+
+    /// 优雅关停：不再接新连接，通知所有在线用户，等连接收尾（最多 shutdown_grace_secs），最后把日志写完。
+    /// 只有第一次调用会做这些，之后的直接返回
+    pub async fn shutdown(&self) {
+        if self.inner.stopping.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let mut conns = std::mem::take(&mut *self.inner.conns.lock().unwrap());
+        while conns.try_join_next().is_some() {} // 已经结束、只是还没回收的不算
+        println!("Shutting down: {} connections open", conns.len());
+        notify_all(&self.inner.state, system("Server is shutting down, bye!")).await;
+        self.inner.shutdown.send_replace(true);
+
+        let grace = self.inner.config.shutdown_grace();
+        let drained = timeout(grace, async { while conns.join_next().await.is_some() {} }).await;
+        if drained.is_err() {
+            eprintln!("! {} connections still open after {}s, aborting", conns.len(), grace.as_secs());
+            conns.shutdown().await;
+        }
+
+        // 连接都结束了：换掉 State 里的日志通道，写任务收到通道关闭，写完剩余记录后退出
+        self.inner.state.lock().await.log_tx = mpsc::unbounded_channel().0;
+        if let Some(task) = self.inner.log_task.lock().await.take() {
+            let _ = task.await;
+        }
+    }
+
+    /// 新连接能不能进：关停中、被封、满了都不行，返回给对方的那句话
+    async fn admit(&self, peer: Peer) -> Result<OwnedSemaphorePermit, String> {
+        if self.inner.stopping.load(Ordering::SeqCst) {
+            return Err("** Server is shutting down\n".to_string());
+        }
+        // 被封禁的 IP 在进 handle_conn 之前就拒掉
+        let banned = self.inner.state.lock().await.bans.check(peer.ip()).map(|ban| match ban.remaining() {
+            Some(left) => format!(" for another {}", format_duration(left)),
+            None => String::new(),
+        });
+        if let Some(left) = banned {
+            println!("x Rejected {peer}: banned");
+            return Err(format!("** You are banned from this server{left}\n"));
+        }
+        let Ok(permit) = self.inner.slots.clone().try_acquire_owned() else {
+            println!("x Rejected {peer}: {} connections already open", self.inner.config.max_connections);
+            return Err("** Server full, try again later\n".to_string());
+        };
+        println!("+ Client connected: {peer}");
+        Ok(permit)
+    }
+
+    /// 在后台跑一个连接：stream 完成（TLS 握手）后交给 handle_conn，结束时清理
+    fn spawn<S, F>(&self, peer: Peer, permit: OwnedSemaphorePermit, stream: F)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+        F: Future<Output = io::Result<S>> + Send + 'static,
+    {
+        let state = self.inner.state.clone();
+        let commands = self.inner.commands.clone();
+        let shutdown = self.inner.shutdown.subscribe();
+        let mut conns = self.inner.conns.lock().unwrap();
+        while conns.try_join_next().is_some() {} // 顺手回收已经结束的连接任务
+        conns.spawn(async move {
+            let _permit = permit;
+            let result = match stream.await {
+                Ok(stream) => handle_conn(stream, peer, state.clone(), commands, shutdown).await,
+                Err(e) => Err(e), // 握手失败
+            };
+            if let Err(e) = result {
+                eprintln!("! Connection {peer} error: {e}");
+            }
+
+            // 连接结束：清理状态并向所在房间广播离开（并写入历史）
+            // 还没发首行就断开（或关停时还在握手）的连接没进过房间，不用广播
+            if let Some((name, room)) = remove_user(&state, peer).await {
+                let msg = ServerFrame::Leave { room: room.clone(), nick: name.clone() };
+                broadcast_to_room(&state, &room, peer, &name, msg).await;
+            }
+            println!("- Client disconnected: {peer}");
+        });
+    }
+}
+
+/// 拒绝连接：给句提示再关
+fn refuse<S: AsyncWrite + Send + 'static>(socket: S, msg: String) {
+    tokio::spawn(async move {
+        let mut socket = Box::pin(socket);
+        let _ = socket.write_all(msg.as_bytes()).await;
+    });
+}
+
+/// 处理一个连接；明文 TcpStream 和 TLS 流都走这里
+async fn handle_conn<S>(
+    socket: S,
+    peer: Peer,
+    state: SharedState,
+    commands: Arc<Registry>,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    // 拆分读写半端；按行读取时限制行长
+    let config = state.lock().await.config.clone();
+    let (reader, writer) = io::split(socket);
+    let mut lines = BoundedLines::new(BufReader::new(reader), config.max_line_len);
+
+    // 首行可以是协议协商：`/proto json` 切到 JSON 帧，否则就是纯文本老客户端。
+    // 进房间之前还可以发 `/since <id>`（重连用）：历史只补发这个 ID 之后的，而不是最近 N 条
+    // 握手整体算一次空闲超时：光连上不说话的连接不能一直占着名额
+    let mut json = false;
+    let mut since = None;
+    let handshake_deadline = Instant::now() + config.idle_timeout();
+    let first = loop {
+        let line = tokio::select! {
+            res = timeout_at(handshake_deadline, lines.next_line()) => res.unwrap_or(Ok(None))?,
+            _ = shutdown.changed() => None,
+        };
+        let Some(RawLine::Text(line)) = line else {
+            return Ok(()); // 未输入任何内容即断开（或超时、服务器关停）；第一行就超长的也直接断开
+        };
+        if !json && since.is_none() && line.trim() == PROTO_JSON {
+            json = true;
+            continue;
+        }
+        let line = match decode_input(line, json) {
+            Input::Line(line) => line,
+            _ => String::new(),
+        };
+        if let Some(id) = parse_since(&line) {
+            since = Some(id);
+            continue;
+        }
+        break line;
+    };
+
+    // 私聊队列：往这个 sender 发的消息只写给该连接
+    let (priv_tx, mut priv_rx) = Outbox::new(config.outbound_queue);
+
+    // 管理员的踢出、禁言由这个连接自己执行
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel::<Control>();
+
+    // 换房间时把新房间的订阅交给写任务
+    let (switch_tx, mut switch_rx) = mpsc::unbounded_channel::<RoomRx>();
+
+    // 连接收尾时通知写任务：把私聊队列里剩下的写完再关
+    let (close_tx, mut close_rx) = oneshot::channel::<()>();
+
+    // 已发出但还没收到 PONG 的 PING 数：写任务每次心跳 +1，读到 PONG 清零
+    let unanswered = Arc::new(AtomicU32::new(0));
+
+    // 写任务：同时消费【房间广播】与【私聊队列】，按协议模式编码后写回
+    let mut rx_for_writer = subscribe_room(&state, LOBBY).await;
+    let mut heartbeat = interval(config.heartbeat());
+    let max_missed = config.max_missed_heartbeats;
+    let pings = unanswered.clone();
+    let dropped = priv_tx.dropped.clone();
+    let policy = config.slow_consumer_policy;
+    let max_strikes = config.slow_consumer_strikes;
+    // 写不出去（对方完全不读、TCP 缓冲满）时心跳也发不了，只能靠单次写的时限判断
+    let stall = (policy == SlowConsumerPolicy::Disconnect).then(|| config.heartbeat() * max_strikes);
+    let mut write_task = tokio::spawn(async move {
+        let mut w = writer; // 移动所有权
+        let mut room_open = true; // 旧房间被回收时广播会关闭，等换房间的新订阅
+        let mut strikes = 0; // 连续几个心跳周期都在丢消息
+        loop {
+            let frame = tokio::select! {
+                // 收房间消息（排除自己）；落后太多时广播会跳过旧消息，记下跳过的条数，心跳时统一提示
+                res = rx_for_writer.recv(), if room_open => match res {
+                    Ok((from, frame)) => {
+                        if from == peer { continue; }
+                        frame
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        dropped.fetch_add(n.try_into().unwrap_or(u32::MAX), Ordering::Relaxed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        room_open = false;
+                        continue;
+                    }
+                },
+                // 切换房间：丢弃旧订阅
+                Some(new_rx) = switch_rx.recv() => {
+                    rx_for_writer = new_rx;
+                    room_open = true;
+                    continue;
+                }
+                // 收到给自己的私聊
+                Some(pm) = priv_rx.recv() => pm,
+                // 收尾：写完剩下的私聊（超时提示、关停通知……）后关闭写半端
+                _ = &mut close_rx => {
+                    while let Ok(pm) = priv_rx.try_recv() {
+                        if write_frame(&mut w, &pm, json, stall).await.is_err() { break; }
+                    }
+                    let _ = w.shutdown().await;
+                    break;
+                }
+
+                // 定时心跳：连续 max_missed 个 PING 没有回应就判定掉线，写任务结束
+                _ = heartbeat.tick() => {
+                    if pings.fetch_add(1, Ordering::Relaxed) >= max_missed {
+                        let bye = system(format!("No reply to {max_missed} heartbeats, disconnecting."));
+                        let _ = write_frame(&mut w, &bye.into(), json, stall).await;
+                        break;
+                    }
+                    // 这个周期里广播落后或私聊队列满丢掉的消息
+                    let missed = dropped.swap(0, Ordering::Relaxed);
+                    if missed == 0 {
+                        strikes = 0;
+                    } else {
+                        strikes += 1;
+                        if policy == SlowConsumerPolicy::Disconnect && strikes >= max_strikes {
+                            let bye = system(format!("Too slow: missed {missed} more messages, disconnecting."));
+                            let _ = write_frame(&mut w, &bye.into(), json, stall).await;
+                            break;
+                        }
+                        let notice = system(format!("You are reading too slowly and missed {missed} messages"));
+                        if write_frame(&mut w, &notice.into(), json, stall).await.is_err() { break; }
+                    }
+                    ServerFrame::Ping.into()
+                }
+                else => break,
+            };
+            if let Err(e) = write_frame(&mut w, &frame, json, stall).await {
+                if e.kind() == io::ErrorKind::TimedOut {
+                    eprintln!("! {peer} stopped reading, disconnecting");
+                }
+                break;
+            }
+        }
+    });
+
+    // 处理首条输入：如果是 /nick 则尝试设置，否则注册默认名（用地址），首条交给下面的循环处理
+    let mut pending = None;
+    let name = if let Some(nick) = first.strip_prefix("/nick ").and_then(parse_nick) {
+        match try_set_nick(&state, peer, nick.to_string(), priv_tx.clone(), control_tx.clone()).await {
+            Ok(ok_name) => ok_name,
+            Err(e) => {
+                // 昵称不可用：注册默认地址名并提示
+                let name = register_default(&state, peer, priv_tx.clone(), control_tx.clone()).await;
+                let _ = priv_tx.send(error(format!("{}. You are {name}", e.describe(nick))));
+                name
+            }
+        }
+    } else {
+        pending = Some(first);
+        register_default(&state, peer, priv_tx.clone(), control_tx.clone()).await
+    };
+    if let Some(user) = state.lock().await.by_addr.get_mut(&peer) {
+        user.json = json;
+    }
+    let mut session = Session {
+        peer,
+        state: state.clone(),
+        config: config.clone(),
+        name,
+        room: LOBBY.to_string(),
+        out: priv_tx.clone(),
+        switch: switch_tx,
+        mine: VecDeque::new(),
+        commands,
+    };
+
+    // 广播加入 & 记历史
+    session.broadcast(ServerFrame::Join { room: session.room.clone(), nick: session.name.clone() }).await;
+
+    // 发送历史消息给新加入的用户，再投递离线私聊
+    send_history_to_user(&state, &session.room, &priv_tx, since).await;
+    send_topic(&state, &session.room, &priv_tx).await;
+    send_members(&state, &session.room, &priv_tx).await;
+    deliver_mail(&state, &session.name, &priv_tx).await;
+
+    // 后续循环：每行交给命令表（/命令 或 群聊）；加入空闲超时逻辑
+    // 空闲计时只看真正的输入，PONG 不算
+    let mut idle_deadline = Instant::now() + config.idle_timeout();
+    let mut writer_done = false;
+    let mut flood = FloodGuard::new(&config);
+    loop {
+        let input = match pending.take() {
+            Some(line) => Input::Line(line),
+            None => {
+                let next = tokio::select! {
+                    res = timeout_at(idle_deadline, lines.next_line()) => res,
+                    // 写任务结束（心跳超时或写失败），连接也就结束了
+                    _ = &mut write_task => {
+                        writer_done = true;
+                        break;
+                    }
+                    // 服务器关停（通知已经放进私聊队列）
+                    _ = shutdown.changed() => break,
+                    // 管理员踢出或禁言
+                    Some(ctl) = control_rx.recv() => match ctl {
+                        Control::Kick(why) => {
+                            let _ = priv_tx.send(error(why));
+                            break;
+                        }
+                        Control::Mute { until, notice } => {
+                            flood.mute(until);
+                            let _ = priv_tx.send(system(notice));
+                            continue;
+                        }
+                    },
+                };
+                match next {
+                    Ok(res) => match res? {
+                        Some(RawLine::Text(raw)) => decode_input(raw, json),
+                        Some(RawLine::TooLong) => Input::TooLong,
+                        None => break, // 客户端正常断开
+                    },
+                    Err(_) => {
+                        // 超时：通知一下用户并断开
+                        let _ = priv_tx.send(system(format!(
+                            "Idle timeout: no input for {}s, disconnecting.",
+                            config.idle_timeout_secs
+                        )));
+                        break;
+                    }
+                }
+            }
+        };
+
+        // 心跳回应是控制消息，不当聊天，也不限速
+        if let Input::Pong = input {
+            unanswered.store(0, Ordering::Relaxed);
+            continue;
+        }
+
+        // 防刷屏：超速或超长先警告，再禁言，再断开。文件数据有自己的窗口，不算；发起传输和不该来的帧还是算
+        let expected = match &input {
+            Input::File(frame) => state.lock().await.transfers.expects(peer, frame),
+            _ => false,
+        };
+        let verdict = match &input {
+            Input::TooLong => flood.too_long(),
+            Input::File(_) if expected => Verdict::Allow,
+            _ => flood.check(),
+        };
+        match verdict {
+            Verdict::Allow => {}
+            Verdict::Reject(why) => {
+                let _ = priv_tx.send(error(why));
+                continue;
+            }
+            Verdict::Disconnect(why) => {
+                let _ = priv_tx.send(error(why));
+                break;
+            }
+        }
+
+        let line = match input {
+            Input::Line(line) => line.trim().to_string(),
+            Input::Invalid(why) => {
+                let _ = priv_tx.send(error(format!("Bad frame: {why}")));
+                continue;
+            }
+            // 传文件不算说话（/whois 的空闲时间不变），但连接是活的
+            Input::File(frame) => {
+                idle_deadline = Instant::now() + config.idle_timeout();
+                if let Err(e) = relay_file(&state, peer, frame).await {
+                    let _ = priv_tx.send(error(e));
+                }
+                continue;
+            }
+            Input::Pong | Input::TooLong => continue, // 上面已经处理
+        };
+        if let Some(user) = state.lock().await.by_addr.get_mut(&peer) {
+            user.last_active = Instant::now();
+        }
+        idle_deadline = Instant::now() + config.idle_timeout();
+        if line.is_empty() { continue; }
+        session.commands.clone().handle_line(&mut session, &line).await;
+    }
+
+    // 让写任务把队列里剩下的写完；对端不读就别等太久
+    if !writer_done {
+        let _ = close_tx.send(());
+        if timeout(Duration::from_secs(2), &mut write_task).await.is_err() {
+            write_task.abort();
+        }
+    }
+    Ok(())
+}
+
+// === 协议编解码 ===
+
+/// 读到的一行输入
+enum Input {
+    Line(String),
+    Pong,
+    Invalid(String),
+    TooLong,           // 超过 max_line_len，内容已丢弃
+    File(ClientFrame), // 文件传输的帧（只有 JSON 模式有）
+}
+
+/// 按协议模式解码一行：纯文本里单独的 `PONG` 是心跳回应，其余原样当作输入；JSON 模式解析 ClientFrame
+fn decode_input(raw: String, json: bool) -> Input {
+    if !json {
+        if raw.trim() == "PONG" {
+            return Input::Pong;
+        }
+        return Input::Line(scrub(&raw));
+    }
+    match serde_json::from_str::<ClientFrame>(&raw) {
+        Ok(ClientFrame::Line { text }) => Input::Line(scrub(&text)),
+        Ok(ClientFrame::Pong) => Input::Pong,
+        Ok(frame) => Input::File(frame),
+        Err(e) => Input::Invalid(e.to_string()),
+    }
+}
+
+/// 控制字符换成空格：JSON 里的 `\n` 原样转给纯文本客户端，就成了伪造的服务器通知或私聊行
+fn scrub(text: &str) -> String {
+    text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+/// 写一帧；给了 limit 时超时算对方卡死，返回 TimedOut
+async fn write_frame<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    frame: &Envelope,
+    json: bool,
+    limit: Option<Duration>,
+) -> io::Result<()> {
+    let line = encode_frame(frame, json);
+    let write = w.write_all(line.as_bytes());
+    match limit {
+        Some(limit) => timeout(limit, write).await.map_err(|_| io::ErrorKind::TimedOut)?,
+        None => write.await,
+    }
+}
+
+/// 按协议模式编码一帧（带换行）；纯文本模式把 ID 和 UTC 时间放在行首
+fn encode_frame(env: &Envelope, json: bool) -> String {
+    let mut line = if json {
+        serde_json::to_string(env).expect("Envelope always serializes")
+    } else {
+        let stamp = env.stamp.map(|s| format!("[#{} {}] ", s.id, s.ts.format("%H:%M:%SZ"))).unwrap_or_default();
+        match &env.frame {
+            ServerFrame::History { frame } => format!("[history] {stamp}{}", to_text(frame)),
+            frame => format!("{stamp}{}", to_text(frame)),
+        }
+    };
+    line.push('\n');
+    line
+}
+
+/// 纯文本模式下的渲染，保持老客户端看到的格式
+fn to_text(frame: &ServerFrame) -> String {
+    // 大厅里的进出不带房间名，和多房间之前一样
+    let tag = |room: &str| if room == LOBBY { String::new() } else { format!(" #{room}") };
+    // 插进去的字段都是用户给的，最后整行再洗一遍，一帧只能是一行
+    let line = match frame {
+        ServerFrame::Chat { from, text, .. } => format!("[{from}] {text}"),
+        ServerFrame::Action { from, text, .. } => format!("* {from} {text}"),
+        ServerFrame::Topic { room, by, text } => format!("-- {by} set the topic of #{room}: {text}"),
+        ServerFrame::Edit { target, from, text, .. } => format!("-- {from} edited #{target}: {text}"),
+        ServerFrame::Delete { target, from, .. } => format!("-- {from} deleted #{target}"),
+        ServerFrame::Join { room, nick } => format!("-- {nick} joined{}", tag(room)),
+        ServerFrame::Leave { room, nick } => format!("-- {nick} left{}", tag(room)),
+        ServerFrame::Nick { old, new } => format!("-- {old} -> {new}"),
+        ServerFrame::Members { room, members } => format!("** In #{room} ({}): {}", members.len(), describe_members(members)),
+        ServerFrame::Whisper { from, text } => format!("[whisper from {from}] {text}"),
+        ServerFrame::WhisperSent { to, text } => format!("[whisper to {to}] {text}"),
+        ServerFrame::OfflineWhisper { from, text, .. } => format!("[whisper from {from}, while you were away] {text}"),
+        ServerFrame::System { text } | ServerFrame::Error { text } => format!("** {text}"),
+        ServerFrame::History { frame } => format!("[history] {}", to_text(frame)),
+        ServerFrame::Ping => "PING".to_string(),
+        // 纯文本客户端收发不了文件，这几帧到不了它们那里；写个能看的样子
+        ServerFrame::FileOffer { transfer, from, name, size, .. } => {
+            format!("** {from} offers {name} ({size} bytes), transfer {transfer}")
+        }
+        ServerFrame::FileOffered { transfer, to, name, .. } => format!("** Offered {name} to {to}, transfer {transfer}"),
+        ServerFrame::FileAccepted { transfer, by, .. } => format!("** {by} accepted transfer {transfer}"),
+        ServerFrame::FileChunk { transfer, seq, .. } => format!("** Transfer {transfer}: chunk {seq}"),
+        ServerFrame::FileAck { transfer, seq } => format!("** Transfer {transfer}: chunk {seq} received"),
+        ServerFrame::FileDone { transfer, .. } => format!("** Transfer {transfer} finished"),
+        ServerFrame::FileCancelled { transfer, by, reason } => format!("** {by} cancelled transfer {transfer}: {reason}"),
+    };
+    scrub(&line)
+}
+
+fn chat(room: &str, from: &str, text: &str) -> ServerFrame {
+    ServerFrame::Chat { room: room.to_string(), from: from.to_string(), text: text.to_string() }
+}
+
+fn system(text: impl Into<String>) -> ServerFrame {
+    ServerFrame::System { text: text.into() }
+}
+
+fn error(text: impl Into<String>) -> ServerFrame {
+    ServerFrame::Error { text: text.into() }
+}
+
+// === 房间与历史缓存相关 ===
+
+/// 向房间广播一条消息，并写入该房间历史和日志；在锁里分配 ID，保证全局递增。返回分配的 ID
+async fn broadcast_to_room(state: &SharedState, room: &str, from: Peer, sender: &str, frame: ServerFrame) -> u64 {
+    let mut st = state.lock().await;
+    st.last_id += 1;
+    let id = st.last_id;
+    let env = Envelope { stamp: Some(Stamp { id, ts: Utc::now() }), frame };
+    if let Some(r) = st.rooms.get(room) {
+        let _ = r.tx.send((from, env.clone()));
+    }
+    let _ = st.log_tx.send(LogRecord::now(room, sender, &env));
+    let cap = st.config.history_cap;
+    let h = st.history.entry(room.to_string()).or_default();
+    // 编辑、删除直接改历史里的原消息，自己不占位置
+    if !chat_log::revise(h, &env.frame) {
+        if h.len() == cap {
+            h.pop_front();
+        }
+        h.push_back(env);
+    }
+    id
+}
+
+/// 回放房间历史：默认全部（最近 history_cap 条）；给了 since 只发 ID 更大的
+async fn send_history_to_user(state: &SharedState, room: &str, tx: &Outbox, since: Option<u64>) {
+    let st = state.lock().await;
+    let Some(h) = st.history.get(room) else { return };
+    // 缓存满了且最老的一条也比 since 新：中间可能有被挤掉的
+    let oldest = h.front().and_then(|env| env.stamp).map_or(0, |s| s.id);
+    if since.is_some_and(|id| h.len() == st.config.history_cap && oldest > id + 1) {
+        let _ = tx.send(system(format!("Only the last {} messages are kept; some may be missing", h.len())));
+    }
+    let newer = |env: &&Envelope| since.is_none_or(|id| env.stamp.is_some_and(|s| s.id > id));
+    for env in h.iter().filter(newer) {
+        // 保留原消息的 ID 和时间；忽略发送失败（断开）
+        let _ = tx.send(Envelope { stamp: env.stamp, frame: ServerFrame::History { frame: Box::new(env.frame.clone()) } });
+    }
+}
+
+/// 进房间时告诉用户当前话题（没有就不说）
+async fn send_topic(state: &SharedState, room: &str, tx: &Outbox) {
+    let st = state.lock().await;
+    if let Some((text, by)) = st.topics.get(room) {
+        let _ = tx.send(system(format!("Topic for #{room}: {text} (set by {by})")));
+    }
+}
+
+/// 房间历史缓存里还有没有这条消息
+async fn in_history(state: &SharedState, room: &str, id: u64) -> bool {
+    let st = state.lock().await;
+    st.history.get(room).is_some_and(|h| h.iter().any(|env| env.stamp.is_some_and(|s| s.id == id)))
+}
+
+/// 改话题（只记下来，广播由调用方做）。房间里的人都能改
+async fn set_topic(state: &SharedState, room: &str, by: &str, text: &str) {
+    let mut st = state.lock().await;
+    st.topics.insert(room.to_string(), (text.to_string(), by.to_string()));
+}
+
+/// 订阅某个房间（不存在则创建）
+async fn subscribe_room(state: &SharedState, room: &str) -> RoomRx {
+    let mut st = state.lock().await;
+    st.room_mut(room).tx.subscribe()
+}
+
+/// 把用户移到另一个房间，返回新房间的订阅和他是否成了房间管理员。旧房间空了就删除（大厅除外）
+async fn move_to_room(state: &SharedState, peer: Peer, room: &str) -> (RoomRx, bool) {
+    let mut st = state.lock().await;
+    let old = match st.by_addr.get_mut(&peer) {
+        Some(user) => std::mem::replace(&mut user.room, room.to_string()),
+        None => LOBBY.to_string(),
+    };
+    leave_room(&mut st, &old, peer);
+    let r = st.room_mut(room);
+    // 第一个进来的人当管理员（大厅只有服务器管理员）
+    let is_op = r.members.is_empty() && room != LOBBY;
+    if is_op {
+        r.ops.insert(peer);
+    }
+    r.members.insert(peer);
+    (r.tx.subscribe(), is_op)
+}
+
+/// 从房间成员中移除；空房间（非大厅）直接回收
+fn leave_room(st: &mut State, room: &str, peer: Peer) {
+    if let Some(r) = st.rooms.get_mut(room) {
+        r.members.remove(&peer);
+        r.ops.remove(&peer);
+        if r.members.is_empty() && room != LOBBY {
+            st.rooms.remove(room);
+        }
+    }
+}
+
+/// 房间列表，形如 `#lobby (3), #rust (1)`
+async fn list_rooms(state: &SharedState) -> String {
+    let st = state.lock().await;
+    let mut rooms: Vec<_> = st.rooms.iter().map(|(name, r)| (name.as_str(), r.members.len())).collect();
+    rooms.sort();
+    rooms
+        .iter()
+        .map(|(name, n)| format!("#{name} ({n})"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// === 指令解析与状态操作 ===
+
+// 下面的 parse_xxx 都只拿命令后面的参数部分（命令名已经由 commands::Registry 分发掉了）
+
+/// 解析 `/nick` 的 `<name>`
+fn parse_nick(arg: &str) -> Option<&str> {
+    arg.split_whitespace().next()
+}
+
+/// 解析 `/w` 的 `<name> <msg>`
+fn parse_whisper(arg: &str) -> Option<(&str, &str)> {
+    let (to, msg) = arg.trim().split_once(char::is_whitespace)?;
+    let msg = msg.trim();
+    if msg.is_empty() { return None; }
+    Some((to, msg))
+}
+
+/// 解析房间名（可带 `#`），只允许字母数字和 `-` `_`
+fn parse_room(arg: &str) -> Option<String> {
+    let name = arg.trim();
+    let name = name.strip_prefix('#').unwrap_or(name);
+    is_valid_name(name).then(|| name.to_ascii_lowercase())
+}
+
+/// 解析进房间前的 `/since <id>`
+fn parse_since(s: &str) -> Option<u64> {
+    s.strip_prefix("/since")?.strip_prefix(char::is_whitespace)?.trim().parse().ok()
+}
+
+/// 解析 `/register` `/login` 的 `<name> <password>`，密码取余下整段
+fn parse_credentials(arg: &str) -> Option<(&str, &str)> {
+    let (name, password) = arg.trim().split_once(char::is_whitespace)?;
+    let password = password.trim();
+    if password.is_empty() { return None; }
+    Some((name, password))
+}
+
+/// 房间名和注册昵称的规则：非空、不超长、只含字母数字和 `-` `_`
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= NAME_MAX
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 尝试设置昵称（首次注册）。成功返回最终昵称。
+async fn try_set_nick(
+    state: &SharedState,
+    peer: Peer,
+    name: String,
+    tx: Outbox,
+    control: mpsc::UnboundedSender<Control>,
+) -> Result<String, NickError> {
+    let mut st = state.lock().await;
+    st.check_nick(peer, &name)?;
+    insert_user(&mut st, peer, name.clone(), tx, control);
+    Ok(name)
+}
+
+/// 注册默认昵称（用地址字符串；同一个地址已经有人用了就带上连接编号），返回这个名字
+async fn register_default(
+    state: &SharedState,
+    peer: Peer,
+    tx: Outbox,
+    control: mpsc::UnboundedSender<Control>,
+) -> String {
+    let mut st = state.lock().await;
+    let mut name = peer.to_string();
+    if st.by_name.contains_key(&name) {
+        name = format!("{name}-{}", peer.conn);
+    }
+    insert_user(&mut st, peer, name.clone(), tx, control);
+    name
+}
+
+/// 登记用户并放进大厅
+fn insert_user(st: &mut State, peer: Peer, name: String, tx: Outbox, control: mpsc::UnboundedSender<Control>) {
+    st.by_name.insert(name.clone(), peer);
+    let now = Instant::now();
+    let user = User {
+        name,
+        room: LOBBY.to_string(),
+        account: None,
+        tx,
+        control,
+        connected: now,
+        last_active: now,
+        away: None,
+        json: false,
+    };
+    st.by_addr.insert(peer, user);
+    if let Some(lobby) = st.rooms.get_mut(LOBBY) {
+        lobby.members.insert(peer);
+    }
+}
+
+/// 移除用户，返回 (昵称, 所在房间)
+async fn remove_user(state: &SharedState, peer: Peer) -> Option<(String, String)> {
+    let mut st = state.lock().await;
+    let User { name, room, .. } = st.by_addr.remove(&peer)?;
+    st.by_name.remove(&name);
+    st.mark_seen(&name);
+    leave_room(&mut st, &room, peer);
+    // 传到一半的文件告诉另一方
+    for (transfer, other) in st.transfers.drop_peer(peer) {
+        if let Some(user) = st.by_addr.get(&other) {
+            let reason = "disconnected".to_string();
+            let _ = user.tx.send(ServerFrame::FileCancelled { transfer, by: name.clone(), reason });
+        }
+    }
+    Some((name, room))
+}
+
+/// 尝试修改昵称。成功返回新昵称。
+async fn try_change_nick(state: &SharedState, peer: Peer, new_name: String) -> Result<String, NickError> {
+    let mut st = state.lock().await;
+
+    // 新昵称被占用或属于别人的账号
+    st.check_nick(peer, &new_name)?;
+
+    // 先拿旧名（只读拷贝，避免可变借用冲突）
+    let old_name = match st.by_addr.get(&peer) {
+        Some(user) => user.name.clone(),
+        None => return Err(NickError::Taken),
+    };
+
+    // 更新 name -> addr 映射；旧名字也算“最近在线”，还能收离线私聊
+    st.by_name.remove(&old_name);
+    st.mark_seen(&old_name);
+    st.by_name.insert(new_name.clone(), peer);
+
+    // 再更新 addr -> user.name
+    if let Some(user) = st.by_addr.get_mut(&peer) {
+        user.name = new_name.clone();
+    }
+    Ok(new_name)
+}
+
+/// 注册账号并让当前连接登录该账号
+async fn register_account(state: &SharedState, peer: Peer, name: &str, password: &str) -> Result<(), String> {
+    if !is_valid_name(name) {
+        return Err(format!("Invalid name '{name}' (letters, digits, '-' or '_', max {NAME_MAX})"));
+    }
+    if password.len() < PASSWORD_MIN {
+        return Err(format!("Password must be at least {PASSWORD_MIN} characters"));
+    }
+    {
+        let st = state.lock().await;
+        if st.accounts.is_registered(name) {
+            return Err(format!("Nick '{name}' is already registered"));
+        }
+        if st.by_name.get(name).is_some_and(|&p| p != peer) {
+            return Err(format!("Nick '{name}' is in use; ask its user to pick another"));
+        }
+    }
+
+    // 哈希很慢，放到阻塞线程池，且不持有锁
+    let password = password.to_string();
+    let cred = tokio::task::spawn_blocking(move || Credential::new(&password))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Cannot create account: {e}"))?;
+
+    let mut st = state.lock().await;
+    // 等待期间可能被别人抢先注册
+    if st.accounts.is_registered(name) {
+        return Err(format!("Nick '{name}' is already registered"));
+    }
+    st.accounts
+        .insert(name, cred)
+        .await
+        .map_err(|e| format!("Cannot save account: {e}"))?;
+    if let Some(user) = st.by_addr.get_mut(&peer) {
+        user.account = Some(name.to_string());
+    }
+    Ok(())
+}
+
+/// 校验密码并让当前连接登录该账号
+async fn login_account(state: &SharedState, peer: Peer, name: &str, password: &str) -> Result<(), String> {
+    let Some(cred) = state.lock().await.accounts.get(name) else {
+        return Err(format!("No account named '{name}'"));
+    };
+
+    let password = password.to_string();
+    let ok = tokio::task::spawn_blocking(move || cred.verify(&password))
+        .await
+        .map_err(|e| e.to_string())?;
+    if !ok {
+        return Err("Wrong password".to_string());
+    }
+
+    let mut st = state.lock().await;
+    if st.by_name.get(name).is_some_and(|&p| p != peer) {
+        return Err(format!("'{name}' is already logged in elsewhere"));
+    }
+    if let Some(user) = st.by_addr.get_mut(&peer) {
+        user.account = Some(name.to_string());
+    }
+    Ok(())
+}
+
+// === 管理命令 ===
+
+/// 解析好的管理命令
+enum ModCommand {
+    Kick { nick: String, reason: Option<String> },
+    Ban { target: String, duration: Option<Duration> }, // target 是昵称或 IP，不给时长就是永久
+    Mute { nick: String, duration: Duration },
+    Unban { target: String },
+}
+
+/// 解析 `/kick` `/ban` `/mute` `/unban` 的参数，参数不对返回 None，时长太长返回提示
+fn parse_mod_command(verb: &str, arg: &str) -> Option<Result<ModCommand, String>> {
+    let (first, rest) = arg.split_once(char::is_whitespace).map_or((arg, ""), |(a, b)| (a, b.trim()));
+    if first.is_empty() {
+        return None;
+    }
+    // 可选的时长参数：给了就必须合法
+    let duration = match rest {
+        "" => Some(Ok(None)),
+        d => parse_duration(d).map(|d| d.map(Some)),
+    };
+    let target = first.to_string();
+    match verb {
+        "/kick" => Some(Ok(ModCommand::Kick { nick: target, reason: (!rest.is_empty()).then(|| rest.to_string()) })),
+        "/ban" => duration.map(|d| d.map(|duration| ModCommand::Ban { target, duration })),
+        "/mute" => duration.map(|d| d.map(|d| ModCommand::Mute { nick: target, duration: d.unwrap_or(MUTE_DEFAULT) })),
+        "/unban" if rest.is_empty() => Some(Ok(ModCommand::Unban { target })),
+        _ => None,
+    }
+}
+
+/// 执行管理命令（是不是管理员已经按命令声明的权限查过），成功返回给执行者的确认；被处理的人所在房间会收到一条公告
+async fn moderate(state: &SharedState, peer: Peer, actor: &str, cmd: ModCommand) -> Result<String, String> {
+    let mut st = state.lock().await;
+    let (done, notices) = match cmd {
+        ModCommand::Kick { nick, reason } => {
+            let (_, user) = moderation_target(&st, peer, &nick)?;
+            let why = reason.map(|r| format!(": {r}")).unwrap_or_default();
+            let _ = user.control.send(Control::Kick(format!("You were kicked by {actor}{why}")));
+            let notice = (user.room.clone(), format!("{nick} was kicked by {actor}{why}"));
+            (format!("Kicked {nick}"), vec![notice])
+        }
+        ModCommand::Mute { nick, duration } => {
+            let (_, user) = moderation_target(&st, peer, &nick)?;
+            let how_long = format_duration(duration);
+            let until = Instant::now().checked_add(duration).ok_or("Duration is too long")?;
+            let notice = format!("You were muted by {actor} for {how_long}");
+            let _ = user.control.send(Control::Mute { until, notice });
+            let notice = (user.room.clone(), format!("{nick} was muted by {actor} for {how_long}"));
+            (format!("Muted {nick} for {how_long}"), vec![notice])
+        }
+        ModCommand::Ban { target, duration } => {
+            // 按 IP 直接封；按昵称就封他当前的 IP
+            let (ip, nick) = match target.parse::<IpAddr>() {
+                Ok(ip) => (ip, "-"),
+                Err(_) => (moderation_target(&st, peer, &target)?.0.ip(), target.as_str()),
+            };
+            if ip == peer.ip() {
+                return Err("You can't ban your own address".to_string());
+            }
+            st.bans.insert(ip, duration, nick).await.map_err(|e| format!("Cannot save ban: {e}"))?;
+            let how_long = duration.map(|d| format!(" for {}", format_duration(d))).unwrap_or_default();
+            // 这个 IP 上所有在线的连接都踢掉
+            let mut notices = Vec::new();
+            for (_, user) in st.by_addr.iter().filter(|(peer, _)| peer.ip() == ip) {
+                let _ = user.control.send(Control::Kick(format!("You were banned by {actor}{how_long}")));
+                notices.push((user.room.clone(), format!("{} was banned by {actor}{how_long}", user.name)));
+            }
+            (format!("Banned {ip}{how_long}"), notices)
+        }
+        ModCommand::Unban { target } => {
+            let ips = st.bans.remove(&target).await.map_err(|e| format!("Cannot save bans: {e}"))?;
+            if ips.is_empty() {
+                return Err(format!("No ban for '{target}'"));
+            }
+            let ips: Vec<String> = ips.iter().map(IpAddr::to_string).collect();
+            (format!("Unbanned {}", ips.join(", ")), Vec::new())
+        }
+    };
+    drop(st);
+    for (room, text) in notices {
+        broadcast_to_room(state, &room, peer, actor, system(text)).await;
+    }
+    Ok(done)
+}
+
+/// 找到 actor 有权管理的在线用户，返回其地址和信息
+fn moderation_target<'a>(st: &'a State, actor: Peer, nick: &str) -> Result<(Peer, &'a User), String> {
+    let &target = st.by_name.get(nick).ok_or_else(|| format!("User '{nick}' not found"))?;
+    if !st.can_moderate(actor, target) {
+        return Err(format!("You can't moderate {nick}"));
+    }
+    let user = st.by_addr.get(&target).ok_or_else(|| format!("User '{nick}' not found"))?;
+    Ok((target, user))
+}
+
+// === 历史查询 ===
+
+/// 解析 `/history` 的 `[n]` 或 `before <id> [n]`（只查当前房间）
+fn parse_history(arg: &str, room: &str) -> Option<Query> {
+    let words: Vec<&str> = arg.split_whitespace().collect();
+    let mut query = Query { room: room.to_string(), before: None, text: None, from: None, limit: HISTORY_PAGE };
+    let rest = match words.as_slice() {
+        ["before", id, rest @ ..] => {
+            query.before = Some(id.parse().ok()?);
+            rest
+        }
+        rest => rest,
+    };
+    match rest {
+        [] => {}
+        [n] => query.limit = n.parse().ok().filter(|n| (1..=HISTORY_PAGE_MAX).contains(n))?,
+        _ => return None,
+    }
+    Some(query)
+}
+
+/// 解析 `/search` 的 `<text> [from:<nick>] [before:<id>]`（只查当前房间）
+fn parse_search(arg: &str, room: &str) -> Option<Query> {
+    let mut query = Query { room: room.to_string(), before: None, text: None, from: None, limit: HISTORY_PAGE };
+    let mut text = Vec::new();
+    for word in arg.split_whitespace() {
+        if let Some(nick) = word.strip_prefix("from:") {
+            query.from = Some(nick.to_string());
+        } else if let Some(id) = word.strip_prefix("before:") {
+            query.before = Some(id.parse().ok()?);
+        } else {
+            text.push(word);
+        }
+    }
+    // 只按发送者过滤也行，但总得给个条件
+    if text.is_empty() && query.from.is_none() {
+        return None;
+    }
+    query.text = (!text.is_empty()).then(|| text.join(" ").to_lowercase());
+    Some(query)
+}
+
+/// 执行查询（读日志文件，不占 State 锁），结果只发给本人，最后附上翻页提示
+async fn send_query_results(dir: &Path, query: &Query, tx: &Outbox) {
+    let (hits, more) = match chat_log::search(dir, query).await {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("! History search failed: {e}");
+            let _ = tx.send(error("History is unavailable right now"));
+            return;
+        }
+    };
+    let room = &query.room;
+    let Some(oldest) = hits.first().and_then(|env| env.stamp).map(|s| s.id) else {
+        let _ = tx.send(system(format!("No matching messages in #{room}")));
+        return;
+    };
+    let count = hits.len();
+    for env in hits {
+        let _ = tx.send(Envelope { stamp: env.stamp, frame: ServerFrame::History { frame: Box::new(env.frame) } });
+    }
+    let next = if !more {
+        "no older matches".to_string()
+    } else if let Some(text) = &query.text {
+        let from = query.from.as_ref().map(|nick| format!(" from:{nick}")).unwrap_or_default();
+        format!("older: /search {text}{from} before:{oldest}")
+    } else if let Some(nick) = &query.from {
+        format!("older: /search from:{nick} before:{oldest}")
+    } else {
+        format!("older: /history before {oldest} {}", query.limit)
+    };
+    let _ = tx.send(system(format!("{count} message(s) from #{room}; {next}")));
+}
+
+// === 在线状态 ===
+
+/// 解析好的在线状态命令
+enum PresenceCommand {
+    Who { room: String },
+    Whois { nick: String },
+    Away { message: String },
+    Back,
+}
+
+/// 房间里的人，按名字排序
+fn room_members(st: &State, room: &str) -> Vec<Member> {
+    let Some(r) = st.rooms.get(room) else { return Vec::new() };
+    let mut members: Vec<Member> = r
+        .members
+        .iter()
+        .filter_map(|p| st.by_addr.get(p).map(|u| (p, u)))
+        .map(|(p, u)| Member {
+            nick: u.name.clone(),
+            op: r.ops.contains(p) || st.is_server_op(*p),
+            away: u.away.is_some(),
+        })
+        .collect();
+    members.sort();
+    members
+}
+
+/// 把房间成员列表发给本人
+async fn send_members(state: &SharedState, room: &str, tx: &Outbox) {
+    let members = room_members(&*state.lock().await, room);
+    let _ = tx.send(ServerFrame::Members { room: room.to_string(), members });
+}
+
+/// 执行在线状态命令，返回给本人的回复
+async fn presence(state: &SharedState, peer: Peer, cmd: PresenceCommand) -> Result<String, String> {
+    let mut st = state.lock().await;
+    match cmd {
+        PresenceCommand::Who { room } => {
+            let members = room_members(&st, &room);
+            if members.is_empty() {
+                return Ok(format!("Nobody in #{room}"));
+            }
+            Ok(format!("In #{room} ({}): {}", members.len(), describe_members(&members)))
+        }
+        PresenceCommand::Whois { nick } => {
+            let Some((&target, user)) = st.by_name.get(&nick).and_then(|p| st.by_addr.get_key_value(p)) else {
+                return Err(format!("User '{nick}' not found"));
+            };
+            let mut info = format!(
+                "{nick}: in #{}, connected {} ago, idle {}",
+                user.room,
+                format_duration(user.connected.elapsed()),
+                format_duration(user.last_active.elapsed()),
+            );
+            if let Some(account) = &user.account {
+                info.push_str(&format!(", logged in as {account}"));
+            }
+            if st.is_server_op(target) {
+                info.push_str(", server operator");
+            }
+            if let Some(away) = &user.away {
+                info.push_str(&format!(", away: {away}"));
+            }
+            Ok(info)
+        }
+        PresenceCommand::Away { message } => {
+            let user = st.by_addr.get_mut(&peer).ok_or("Not connected")?;
+            let reply = format!("You are marked as away: {message}");
+            user.away = Some(message);
+            Ok(reply)
+        }
+        PresenceCommand::Back => {
+            let user = st.by_addr.get_mut(&peer).ok_or("Not connected")?;
+            match user.away.take() {
+                Some(_) => Ok("You are no longer marked as away".to_string()),
+                None => Err("You are not away".to_string()),
+            }
+        }
+    }
+}
+
+// === 离线私聊 ===
+
+/// 给不在线的 to 留言，成功返回给发送者的确认
+async fn queue_mail(state: &SharedState, from: &str, to: &str, text: &str) -> Result<String, String> {
+    let mut st = state.lock().await;
+    if !st.can_receive_mail(to) {
+        return Err(format!("User '{to}' not found"));
+    }
+    let limit = st.config.mailbox_size;
+    if st.mailbox.count(to) >= limit {
+        return Err(format!("{to} is offline and their mailbox is full"));
+    }
+    st.mailbox.push(to, from, text).await.map_err(|e| format!("Cannot queue message: {e}"))?;
+    Ok(format!("{to} is offline; message queued ({}/{limit})", st.mailbox.count(to)))
+}
+
+/// 把 nick 的离线私聊全部投递给当前连接
+async fn deliver_mail(state: &SharedState, nick: &str, tx: &Outbox) {
+    let letters = match state.lock().await.mailbox.take(nick).await {
+        Ok(letters) => letters,
+        Err(e) => {
+            eprintln!("! Mailbox write error: {e}");
+            return;
+        }
+    };
+    for letter in letters {
+        let _ = tx.send(ServerFrame::OfflineWhisper { from: letter.from, text: letter.text, sent_at: letter.ts });
+    }
+}
+
+/// 给所有在线用户发一条私聊（比如关停通知）
+async fn notify_all(state: &SharedState, frame: ServerFrame) {
+    let st = state.lock().await;
+    for user in st.by_addr.values() {
+        let _ = user.tx.send(frame.clone());
+    }
+}
+
+/// 按昵称找私聊对象：返回其写队列和 /away 留言
+async fn find_whisper_target(state: &SharedState, name: &str) -> Option<(Outbox, Option<String>)> {
+    let st = state.lock().await;
+    let &peer = st.by_name.get(name)?;
+    let user = st.by_addr.get(&peer)?;
+    Some((user.tx.clone(), user.away.clone()))
+}
+
+// === 文件传输 ===
+
+/// 文件每块几字节：base64 之后（4/3 倍）加上 JSON 外壳要放得进一行输入。行太短时为 0，传不了
+fn file_chunk_size(config: &Config) -> usize {
+    (config.max_line_len.saturating_sub(128) / 4 * 3).min(FILE_CHUNK_MAX)
+}
+
+/// 客户端传文件的帧：检查后转给另一方，服务器不存数据。Err 作为错误提示发给本人
+async fn relay_file(state: &SharedState, peer: Peer, frame: ClientFrame) -> Result<(), String> {
+    let mut st = state.lock().await;
+    let me = st.by_addr.get(&peer).map(|u| u.name.clone()).unwrap_or_default();
+    match frame {
+        ClientFrame::FileOffer { to, name, size } => {
+            let limit = st.config.max_file_size;
+            if limit == 0 || file_chunk_size(&st.config) == 0 {
+                return Err("File transfer is disabled on this server".to_string());
+            }
+            if size > limit {
+                return Err(format!("File is too large ({size} bytes, limit is {limit})"));
+            }
+            let Some(name) = file_name(&name).map(str::to_string) else {
+                return Err(format!("Invalid file name '{name}'"));
+            };
+            let Some(&target) = st.by_name.get(&to) else { return Err(format!("User '{to}' not found")) };
+            if target == peer {
+                return Err("You can't send files to yourself".to_string());
+            }
+            if !st.by_addr.get(&target).is_some_and(|u| u.json) {
+                return Err(format!("{to}'s client can't receive files"));
+            }
+            if st.transfers.count_from(peer) >= FILE_OFFERS_MAX {
+                return Err(format!("Too many transfers in progress (max {FILE_OFFERS_MAX})"));
+            }
+            let transfer = st.transfers.offer(peer, target, size);
+            if !send_to(&st, target, ServerFrame::FileOffer { transfer, from: me, name: name.clone(), size }) {
+                st.transfers.remove(transfer);
+                return Err(format!("{to} is not reading right now; try again later"));
+            }
+            send_to(&st, peer, ServerFrame::FileOffered { transfer, to, name, size });
+        }
+        ClientFrame::FileAccept { transfer } => {
+            let Some(t) = st.transfers.get_mut(transfer).filter(|t| t.to == peer && !t.accepted) else {
+                return Err(format!("No file offer #{transfer} for you"));
+            };
+            t.accepted = true;
+            let from = t.from;
+            let chunk = file_chunk_size(&st.config);
+            // 接收方的写队列还要装聊天消息，窗口只占四分之一
+            let window = (st.config.outbound_queue / 4).clamp(1, FILE_WINDOW_MAX);
+            send_to(&st, from, ServerFrame::FileAccepted { transfer, by: me, chunk, window });
+        }
+        ClientFrame::FileChunk { transfer, seq, data } => {
+            // 对方刚取消时还会有几块在路上，不认识的编号直接丢掉
+            let chunk = file_chunk_size(&st.config);
+            let Some(t) = st.transfers.get_mut(transfer).filter(|t| t.from == peer && t.accepted && !t.done) else {
+                return Ok(());
+            };
+            let len = BASE64.decode(&data).map_or(usize::MAX, |bytes| bytes.len());
+            let problem = if seq != t.next_seq {
+                format!("chunk {seq} out of order (expected {})", t.next_seq)
+            } else if len > chunk || t.sent + len as u64 > t.size {
+                format!("bad chunk {seq}")
+            } else {
+                t.next_seq += 1;
+                t.sent += len as u64;
+                let to = t.to;
+                if send_to(&st, to, ServerFrame::FileChunk { transfer, seq, data }) {
+                    return Ok(());
+                }
+                "receiver is not keeping up".to_string()
+            };
+            cancel_transfer(&mut st, transfer, "server", &problem);
+        }
+        ClientFrame::FileAck { transfer, seq } => {
+            // 只转新的确认：重复的、还没转过的块的确认会把发送方的写队列灌满
+            let Some(t) = st.transfers.get_mut(transfer).filter(|t| t.to == peer && t.acks(seq)) else {
+                return Ok(());
+            };
+            t.last_ack = Some(seq);
+            let from = t.from;
+            send_to(&st, from, ServerFrame::FileAck { transfer, seq });
+        }
+        // 发送方发完转给接收方校验；接收方校验通过再回一个才算传完，在这之前确认还能转过去
+        ClientFrame::FileDone { transfer, sha256 } => {
+            let Some(t) = st.transfers.get_mut(transfer).filter(|t| t.accepted) else { return Ok(()) };
+            if t.from == peer && !t.done {
+                let problem = if t.sent != t.size {
+                    format!("sent {} of {} bytes", t.sent, t.size)
+                } else if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    "bad checksum: expected 64 hex digits of SHA-256".to_string()
+                } else {
+                    t.done = true;
+                    let to = t.to;
+                    send_to(&st, to, ServerFrame::FileDone { transfer, sha256: sha256.to_ascii_lowercase() });
+                    return Ok(());
+                };
+                cancel_transfer(&mut st, transfer, "server", &problem);
+            } else if t.to == peer && t.done {
+                let from = t.from;
+                st.transfers.remove(transfer);
+                send_to(&st, from, ServerFrame::FileDone { transfer, sha256: String::new() });
+            }
+        }
+        ClientFrame::FileCancel { transfer, reason } => {
+            if !st.transfers.get_mut(transfer).is_some_and(|t| t.from == peer || t.to == peer) {
+                return Err(format!("No transfer #{transfer}"));
+            }
+            let reason = if reason.trim().is_empty() { "cancelled" } else { reason.trim() };
+            cancel_transfer(&mut st, transfer, &me, reason);
+        }
+        ClientFrame::Line { .. } | ClientFrame::Pong => unreachable!("decode_input only passes file frames"),
+    }
+    Ok(())
+}
+
+/// 取消一个传输，告诉双方
+fn cancel_transfer(st: &mut State, transfer: u64, by: &str, reason: &str) {
+    let Some(t) = st.transfers.remove(transfer) else { return };
+    for peer in [t.from, t.to] {
+        let frame = ServerFrame::FileCancelled { transfer, by: by.to_string(), reason: reason.to_string() };
+        send_to(st, peer, frame);
+    }
+}
+
+/// 发给某个在线连接；不在线或队列满返回 false
+fn send_to(st: &State, peer: Peer, frame: ServerFrame) -> bool {
+    st.by_addr.get(&peer).is_some_and(|u| u.tx.send(frame))
+}
+
+
+
+/*
+cargo run --bin server
+cargo run --bin server -- --data-dir /tmp/chat
+cargo run --bin server -- --config server.example.toml --bind 0.0.0.0:7000 --max-connections 100
+
+TLS（本地自签名：先生成 CA，再用 CA 签服务器证书）：
+openssl req -x509 -newkey rsa:2048 -nodes -days 365 -subj "/CN=chat-ca" -keyout ca.key -out ca.pem
+openssl req -newkey rsa:2048 -nodes -subj "/CN=localhost" -keyout key.pem -out server.csr
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca.key -CAcreateserial -days 365 -out cert.pem \
+    -extfile <(printf "subjectAltName=DNS:localhost,IP:127.0.0.1")
+cargo run --bin server -- --tls-cert cert.pem --tls-key key.pem
+cargo run --bin client -- 127.0.0.1:7000 --tls --ca ca.pem
+cargo run --bin client -- 127.0.0.1:7000 --tls --insecure   # 不校验证书，仅限测试
+cargo run --bin client -- 127.0.0.1:7000
+*/
@@ -0,0 +1,179 @@
+//! 防刷屏：每个连接一个令牌桶限速 + 单行长度上限
+//!
+//! 违规逐级处理：先警告几次，再临时禁言，禁言过后再犯就断开。
+
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt};
+use tokio::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// 令牌桶：每秒补 `rate` 个，最多攒 `burst` 个
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        TokenBucket { rate: rate as f64, burst: burst as f64, tokens: burst as f64, last: Instant::now() }
+    }
+
+    /// 取一个令牌，没有就返回 false
+    pub fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// 对一行输入的处理结果
+pub enum Verdict {
+    /// 正常处理
+    Allow,
+    /// 丢掉这一行，把提示发给本人
+    Reject(String),
+    /// 断开连接，附带原因
+    Disconnect(String),
+}
+
+/// 每个连接的刷屏检查
+pub struct FloodGuard {
+    bucket: TokenBucket,
+    max_line_len: usize,
+    max_warnings: u32,
+    warnings: u32,                // 已经警告过几次
+    mute_for: Duration,
+    muted_until: Option<Instant>, // 禁言到什么时候
+    was_muted: bool,              // 禁言过一次，再犯就断开
+}
+
+impl FloodGuard {
+    pub fn new(config: &Config) -> Self {
+        FloodGuard {
+            bucket: TokenBucket::new(config.rate_limit_per_sec, config.rate_limit_burst),
+            max_line_len: config.max_line_len,
+            max_warnings: config.flood_warnings,
+            warnings: 0,
+            mute_for: config.flood_mute(),
+            muted_until: None,
+            was_muted: false,
+        }
+    }
+
+    /// 每读到一行调用一次（PONG 除外）
+    pub fn check(&mut self) -> Verdict {
+        if !self.bucket.try_take() {
+            return self.offend("You are sending too fast");
+        }
+        match self.muted_until {
+            Some(until) if Instant::now() < until => {
+                let left = until.saturating_duration_since(Instant::now()).as_secs().max(1);
+                Verdict::Reject(format!("You are muted for {left}s more"))
+            }
+            _ => Verdict::Allow,
+        }
+    }
+
+    /// 被管理员禁言到 until（不算违规，之后刷屏照常升级）
+    pub fn mute(&mut self, until: Instant) {
+        self.muted_until = Some(self.muted_until.map_or(until, |old| old.max(until)));
+    }
+
+    /// 读到一行超长输入
+    pub fn too_long(&mut self) -> Verdict {
+        let what = format!("Line too long (max {} bytes)", self.max_line_len);
+        self.offend(&what)
+    }
+
+    /// 记一次违规，按次数升级处理
+    fn offend(&mut self, what: &str) -> Verdict {
+        if self.was_muted {
+            return Verdict::Disconnect(format!("{what} after being muted, disconnecting."));
+        }
+        if self.warnings < self.max_warnings {
+            self.warnings += 1;
+            return Verdict::Reject(format!("{what}, line dropped (warning {}/{})", self.warnings, self.max_warnings));
+        }
+        self.was_muted = true;
+        self.muted_until = Some(Instant::now() + self.mute_for);
+        Verdict::Reject(format!("{what}, muted for {}s", self.mute_for.as_secs()))
+    }
+}
+
+/// 读到的一行
+pub enum RawLine {
+    Text(String),
+    /// 超过长度上限的行，内容已丢弃
+    TooLong,
+}
+
+/// 带长度上限的按行读取，代替 `BufReader::lines()`：超长的行边读边丢，不会攒在内存里
+pub struct BoundedLines<R> {
+    reader: R,
+    buf: Vec<u8>,
+    max: usize,
+    overflow: bool, // 当前这一行已经超长，丢到换行为止
+}
+
+impl<R: AsyncBufRead + Unpin> BoundedLines<R> {
+    pub fn new(reader: R, max: usize) -> Self {
+        BoundedLines { reader, buf: Vec::new(), max, overflow: false }
+    }
+
+    /// 读下一行（去掉行尾的 `\n` / `\r\n`），EOF 返回 None。
+    /// 进度都存在 self 里，可以放在 select! 里被取消。
+    pub async fn next_line(&mut self) -> io::Result<Option<RawLine>> {
+        loop {
+            let chunk = self.reader.fill_buf().await?;
+            if chunk.is_empty() {
+                // EOF：最后没有换行的半行照样交出去
+                if std::mem::take(&mut self.overflow) {
+                    return Ok(Some(RawLine::TooLong));
+                }
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return self.take_line().map(Some);
+            }
+
+            let newline = chunk.iter().position(|&b| b == b'\n');
+            let part = &chunk[..newline.unwrap_or(chunk.len())];
+            if !self.overflow {
+                if self.buf.len() + part.len() > self.max {
+                    self.overflow = true;
+                    self.buf.clear();
+                } else {
+                    self.buf.extend_from_slice(part);
+                }
+            }
+            let used = newline.map_or(chunk.len(), |i| i + 1);
+            self.reader.consume(used);
+
+            if newline.is_some() {
+                if std::mem::take(&mut self.overflow) {
+                    return Ok(Some(RawLine::TooLong));
+                }
+                return self.take_line().map(Some);
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> io::Result<RawLine> {
+        let mut bytes = std::mem::take(&mut self.buf);
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        String::from_utf8(bytes)
+            .map(RawLine::Text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
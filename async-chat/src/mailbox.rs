@@ -0,0 +1,89 @@
+//! 离线私聊信箱：对方不在线时先存起来，下次有人用这个昵称上线时投递
+//!
+//! 存在数据目录下的 `mailbox.jsonl`，每行一封 JSON。信不多，改动时整个文件重写。
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io;
+
+use crate::chat_log::unix_now;
+
+const MAILBOX_FILE: &str = "mailbox.jsonl";
+
+/// 一封离线私聊
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Letter {
+    pub to: String,
+    pub from: String,
+    pub text: String,
+    pub ts: u64, // 发出时间（unix 秒）
+}
+
+/// 所有人的信箱（内存表 + 文件）
+pub struct Mailbox {
+    path: PathBuf,
+    boxes: HashMap<String, Vec<Letter>>,
+}
+
+impl Mailbox {
+    /// 从数据目录加载；文件不存在就是空的，坏行跳过
+    pub async fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(MAILBOX_FILE);
+        let mut boxes: HashMap<String, Vec<Letter>> = HashMap::new();
+        match fs::read_to_string(&path).await {
+            Ok(text) => {
+                for letter in text.lines().filter_map(|l| serde_json::from_str::<Letter>(l).ok()) {
+                    boxes.entry(letter.to.clone()).or_default().push(letter);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Mailbox { path, boxes })
+    }
+
+    /// 某人信箱里有几封
+    pub fn count(&self, to: &str) -> usize {
+        self.boxes.get(to).map_or(0, Vec::len)
+    }
+
+    /// 存一封信并落盘（容量由调用方先用 count 检查）
+    pub async fn push(&mut self, to: &str, from: &str, text: &str) -> io::Result<()> {
+        let letter = Letter { to: to.to_string(), from: from.to_string(), text: text.to_string(), ts: unix_now() };
+        self.boxes.entry(to.to_string()).or_default().push(letter);
+        if let Err(e) = self.save().await {
+            self.boxes.get_mut(to).map(Vec::pop);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// 取走某人的全部信件；落盘失败就放回去，下次再投
+    pub async fn take(&mut self, to: &str) -> io::Result<Vec<Letter>> {
+        let Some(letters) = self.boxes.remove(to) else { return Ok(Vec::new()) };
+        if let Err(e) = self.save().await {
+            self.boxes.insert(to.to_string(), letters);
+            return Err(e);
+        }
+        Ok(letters)
+    }
+
+    /// 写临时文件再改名
+    async fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut text = String::new();
+        for letter in self.boxes.values().flatten() {
+            text.push_str(&serde_json::to_string(letter).expect("Letter always serializes"));
+            text.push('\n');
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, text).await?;
+        fs::rename(&tmp, &self.path).await
+    }
+}
@@ -0,0 +1,147 @@
+//! 封禁名单：按 IP 封禁，可以带期限，存在数据目录下，重启后依然有效
+//!
+//! 每行一条：`<IP>\t<到期 unix 秒，0 为永久>\t<封禁时的昵称>`。条目很少，改动时整个文件重写。
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::fs;
+use tokio::io;
+
+use crate::chat_log::unix_now;
+
+const BANS_FILE: &str = "bans.txt";
+
+/// 一条封禁
+pub struct Ban {
+    pub until: Option<u64>, // 到期时间（unix 秒），None 为永久
+    pub nick: String,       // 封禁时对方的昵称，/unban 可以按它解封；按 IP 封的为 "-"
+}
+
+impl Ban {
+    /// 还剩多久；永久封禁返回 None
+    pub fn remaining(&self) -> Option<Duration> {
+        self.until.map(|until| Duration::from_secs(until.saturating_sub(unix_now())))
+    }
+
+    fn expired(&self) -> bool {
+        self.until.is_some_and(|until| until <= unix_now())
+    }
+}
+
+/// 所有封禁（内存表 + 文件）
+pub struct Bans {
+    path: PathBuf,
+    bans: HashMap<IpAddr, Ban>,
+}
+
+impl Bans {
+    /// 从数据目录加载；文件不存在就是空表，过期的和坏行直接跳过
+    pub async fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(BANS_FILE);
+        let bans = match fs::read_to_string(&path).await {
+            Ok(text) => text.lines().filter_map(parse_line).filter(|(_, ban)| !ban.expired()).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Bans { path, bans })
+    }
+
+    /// 查某个 IP 是否被封；过期的顺手从内存里去掉（文件下次改动时再清）
+    pub fn check(&mut self, ip: IpAddr) -> Option<&Ban> {
+        if self.bans.get(&ip).is_some_and(Ban::expired) {
+            self.bans.remove(&ip);
+        }
+        self.bans.get(&ip)
+    }
+
+    /// 新增或覆盖一条封禁并落盘
+    pub async fn insert(&mut self, ip: IpAddr, duration: Option<Duration>, nick: &str) -> io::Result<()> {
+        let until = duration.map(|d| unix_now().saturating_add(d.as_secs()));
+        self.bans.insert(ip, Ban { until, nick: nick.to_string() });
+        self.save().await
+    }
+
+    /// 按 IP 或封禁时的昵称解封，返回解封了哪些 IP
+    pub async fn remove(&mut self, target: &str) -> io::Result<Vec<IpAddr>> {
+        let removed: Vec<IpAddr> = match target.parse::<IpAddr>() {
+            Ok(ip) => self.bans.remove(&ip).map(|_| ip).into_iter().collect(),
+            Err(_) => {
+                let ips: Vec<IpAddr> =
+                    self.bans.iter().filter(|(_, ban)| ban.nick == target).map(|(&ip, _)| ip).collect();
+                for ip in &ips {
+                    self.bans.remove(ip);
+                }
+                ips
+            }
+        };
+        if !removed.is_empty() {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    /// 写临时文件再改名，写到一半崩溃也不会留下半个名单
+    async fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let text: String = self
+            .bans
+            .iter()
+            .filter(|(_, ban)| !ban.expired())
+            .map(|(ip, ban)| format!("{ip}\t{}\t{}\n", ban.until.unwrap_or(0), ban.nick))
+            .collect();
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, text).await?;
+        fs::rename(&tmp, &self.path).await
+    }
+}
+
+fn parse_line(line: &str) -> Option<(IpAddr, Ban)> {
+    let mut it = line.trim().splitn(3, '\t');
+    let ip = it.next()?.parse().ok()?;
+    let until = match it.next()?.parse().ok()? {
+        0 => None,
+        t => Some(t),
+    };
+    let nick = it.next().unwrap_or("-").to_string();
+    Some((ip, Ban { until, nick }))
+}
+
+/// 时长最多一年：禁言、封禁再长也没意义，而且算到期时间会溢出
+pub const DURATION_MAX: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// 解析 `30s` / `10m` / `2h` / `7d`，纯数字按秒算；格式不对返回 None，超过 DURATION_MAX 返回提示
+pub fn parse_duration(s: &str) -> Option<Result<Duration, String>> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = num.parse().ok()?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    if n == 0 {
+        return None;
+    }
+    match n.checked_mul(unit).map(Duration::from_secs) {
+        Some(d) if d <= DURATION_MAX => Some(Ok(d)),
+        _ => Some(Err(format!("Duration {s} is too long (max {})", format_duration(DURATION_MAX)))),
+    }
+}
+
+/// 格式化成 `1d 2h 3m 4s`，省略为 0 的部分
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let parts = [(secs / 86400, "d"), (secs / 3600 % 24, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")];
+    let text: Vec<String> = parts.iter().filter(|(n, _)| *n > 0).map(|(n, unit)| format!("{n}{unit}")).collect();
+    if text.is_empty() { "0s".to_string() } else { text.join(" ") }
+}
@@ -0,0 +1,149 @@
+//! 结构化行协议：每行一个 JSON 帧（server 和 client 共用）
+//!
+//! 连接后客户端先发一行 `/proto json` 就切换到 JSON 模式；不发的老客户端继续走纯文本。
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// 协商 JSON 模式的首行
+pub const PROTO_JSON: &str = "/proto json";
+
+/// 服务器 -> 客户端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    /// 房间里的普通聊天
+    Chat { room: String, from: String, text: String },
+    /// `/me` 动作
+    Action { room: String, from: String, text: String },
+    /// 修改房间话题
+    Topic { room: String, by: String, text: String },
+    /// 作者修改了之前的一条消息（target 是原消息的 ID；不叫 id，免得和 Stamp 的字段撞上）
+    Edit { room: String, target: u64, from: String, text: String },
+    /// 作者删除了之前的一条消息
+    Delete { room: String, target: u64, from: String },
+    /// 有人进入房间
+    Join { room: String, nick: String },
+    /// 有人离开房间（换房间或断开）
+    Leave { room: String, nick: String },
+    /// 改昵称
+    Nick { old: String, new: String },
+    /// 发给本人：进了房间（或自己改了名）之后，房间里现在有谁。客户端的成员列表靠它起步
+    Members { room: String, members: Vec<Member> },
+    /// 收到的私聊
+    Whisper { from: String, text: String },
+    /// 自己发出的私聊回显
+    WhisperSent { to: String, text: String },
+    /// 不在线时收到、上线后投递的私聊；sent_at 是发出时间（unix 秒）。不能叫 ts，会和信封里的时间撞上
+    OfflineWhisper { from: String, text: String, sent_at: u64 },
+    /// 服务器提示
+    System { text: String },
+    /// 历史回放，包着当时的那一帧
+    History { frame: Box<ServerFrame> },
+    /// 心跳
+    Ping,
+    /// 命令出错（昵称被占、用户不存在、用法错误……）
+    Error { text: String },
+    /// 发给接收方：有人要传文件给你，`/accept` 接收（transfer 是服务器分配的传输编号）
+    FileOffer { transfer: u64, from: String, name: String, size: u64 },
+    /// 发给发送方：文件已经递给对方，等对方接收
+    FileOffered { transfer: u64, to: String, name: String, size: u64 },
+    /// 发给发送方：对方接收了，按 chunk 字节一块开始发，最多 window 块没确认
+    FileAccepted { transfer: u64, by: String, chunk: usize, window: usize },
+    /// 发给接收方：一块数据（base64）
+    FileChunk { transfer: u64, seq: u64, data: String },
+    /// 发给发送方：接收方写好了第 seq 块
+    FileAck { transfer: u64, seq: u64 },
+    /// 发给接收方：发完了，带上发送方边发边算的 SHA-256（hex）校验；发给发送方：对方校验通过、存好了（sha256 为空）
+    FileDone {
+        transfer: u64,
+        #[serde(default)]
+        sha256: String,
+    },
+    /// 发给双方：传输取消（拒收、断线、出错……）
+    FileCancelled { transfer: u64, by: String, reason: String },
+}
+
+/// 房间成员
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Member {
+    pub nick: String,
+    #[serde(default)]
+    pub op: bool,
+    #[serde(default)]
+    pub away: bool,
+}
+
+/// `a, b (op), c (away)`，server 的 /who 和 client 的显示共用
+pub fn describe_members(members: &[Member]) -> String {
+    let names: Vec<String> = members
+        .iter()
+        .map(|m| {
+            let tags: Vec<&str> = [(m.op, "op"), (m.away, "away")].into_iter().filter(|t| t.0).map(|t| t.1).collect();
+            if tags.is_empty() { m.nick.clone() } else { format!("{} ({})", m.nick, tags.join(", ")) }
+        })
+        .collect();
+    names.join(", ")
+}
+
+/// 传文件时用的文件名：只留路径的最后一段；空的、`.` `..`、太长或带控制字符的不要。
+/// server 转发前和 client 存盘前都过一遍
+pub fn file_name(name: &str) -> Option<&str> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    let ok =
+        !name.is_empty() && name != "." && name != ".." && name.len() <= 255 && !name.chars().any(char::is_control);
+    ok.then_some(name)
+}
+
+/// 房间消息的身份：服务器分配的递增 ID + UTC 时间
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Stamp {
+    pub id: u64,
+    pub ts: DateTime<Utc>,
+}
+
+/// 线上实际发送的一帧：房间消息（含历史回放）带 Stamp，私聊和提示之类的没有。
+/// JSON 里 `id` `ts` 和帧的字段平铺在同一层，不认识它们的老客户端照样能解析。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(flatten)]
+    pub stamp: Option<Stamp>,
+    #[serde(flatten)]
+    pub frame: ServerFrame,
+}
+
+impl From<ServerFrame> for Envelope {
+    fn from(frame: ServerFrame) -> Self {
+        Envelope { stamp: None, frame }
+    }
+}
+
+/// 客户端 -> 服务器（JSON 模式下）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    /// 一行输入：聊天内容或 `/命令`，语义与纯文本模式完全一样
+    Line { text: String },
+    /// 心跳回应
+    Pong,
+    /// 给 to 传文件：先报大小，服务器马上就能按上限拒掉，对方接收后再发数据
+    FileOffer { to: String, name: String, size: u64 },
+    /// 接收别人的文件
+    FileAccept { transfer: u64 },
+    /// 一块数据（base64），seq 从 0 开始
+    FileChunk { transfer: u64, seq: u64, data: String },
+    /// 接收方确认写好了第 seq 块，发送方靠它控制窗口
+    FileAck { transfer: u64, seq: u64 },
+    /// 发送方：全部发完，附上 SHA-256（hex）；接收方（收到服务器的 FileDone 之后）：校验通过、存好了，sha256 留空
+    FileDone {
+        transfer: u64,
+        #[serde(default)]
+        sha256: String,
+    },
+    /// 任何一方取消（接收方拒收也是它）
+    FileCancel {
+        transfer: u64,
+        #[serde(default)]
+        reason: String,
+    },
+}
@@ -0,0 +1,29 @@
+//! 服务端 TLS：从 PEM 文件加载证书链和私钥
+
+use std::{path::Path, sync::Arc};
+use tokio::io;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+/// 用证书链 + 私钥构造 TlsAcceptor
+pub fn load_acceptor(cert_path: &Path, key_path: &Path) -> io::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("{}: {e}", cert_path.display())))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("{}: no certificates found", cert_path.display())));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| invalid(format!("{}: {e}", key_path.display())))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(format!("bad certificate/key pair: {e}")))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,83 @@
+//! 进行中的文件传输：服务器只转发，不落盘，这里记着每个传输是谁发给谁、发到哪了
+//!
+//! 数据按块走双方已有的连接，接收方每写好一块回一个确认，发送方最多领先 window 块，
+//! 所以转发时不会把接收方的私聊写队列塞满。
+
+use crate::protocol::ClientFrame;
+use crate::Peer;
+use std::collections::HashMap;
+
+/// 一个传输
+pub struct Transfer {
+    pub from: Peer,
+    pub to: Peer,
+    pub size: u64,
+    pub sent: u64,             // 已经转发的字节数
+    pub next_seq: u64,         // 下一块应该是第几块
+    pub last_ack: Option<u64>, // 已经转给发送方的最后一个确认
+    pub accepted: bool,
+    pub done: bool, // 发送方发完了，等接收方校验
+}
+
+impl Transfer {
+    /// 确认是不是该转给发送方：已接收、确认的块转发过、比上一个确认新
+    pub fn acks(&self, seq: u64) -> bool {
+        self.accepted && seq < self.next_seq && self.last_ack.is_none_or(|last| seq > last)
+    }
+}
+
+/// 所有进行中的传输，按编号检索
+#[derive(Default)]
+pub struct Transfers {
+    next: u64,
+    active: HashMap<u64, Transfer>,
+}
+
+impl Transfers {
+    /// 登记一个新的报价，返回传输编号（从 1 开始）
+    pub fn offer(&mut self, from: Peer, to: Peer, size: u64) -> u64 {
+        self.next += 1;
+        let transfer = Transfer { from, to, size, sent: 0, next_seq: 0, last_ack: None, accepted: false, done: false };
+        self.active.insert(self.next, transfer);
+        self.next
+    }
+
+    /// 这一帧是不是正常传输里该来的：是的话不算刷屏，重复的确认、乱序的块之类照常计数
+    pub fn expects(&self, peer: Peer, frame: &ClientFrame) -> bool {
+        let get = |id: &u64| self.active.get(id);
+        match frame {
+            ClientFrame::FileAccept { transfer } => get(transfer).is_some_and(|t| t.to == peer && !t.accepted),
+            ClientFrame::FileChunk { transfer, seq, .. } => {
+                get(transfer).is_some_and(|t| t.from == peer && t.accepted && !t.done && *seq == t.next_seq)
+            }
+            ClientFrame::FileAck { transfer, seq } => get(transfer).is_some_and(|t| t.to == peer && t.acks(*seq)),
+            ClientFrame::FileDone { transfer, .. } => {
+                get(transfer).is_some_and(|t| t.accepted && ((t.from == peer && !t.done) || (t.to == peer && t.done)))
+            }
+            ClientFrame::FileCancel { transfer, .. } => get(transfer).is_some_and(|t| t.from == peer || t.to == peer),
+            ClientFrame::FileOffer { .. } | ClientFrame::Line { .. } | ClientFrame::Pong => false,
+        }
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Transfer> {
+        self.active.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Transfer> {
+        self.active.remove(&id)
+    }
+
+    /// 某个连接断开：去掉它参与的所有传输，返回 (编号, 另一方)
+    pub fn drop_peer(&mut self, peer: Peer) -> Vec<(u64, Peer)> {
+        let ids: Vec<u64> =
+            self.active.iter().filter(|(_, t)| t.from == peer || t.to == peer).map(|(&id, _)| id).collect();
+        ids.into_iter()
+            .filter_map(|id| self.active.remove(&id).map(|t| (id, if t.from == peer { t.to } else { t.from })))
+            .collect()
+    }
+
+    /// 某人发起、还没结束的传输有几个
+    pub fn count_from(&self, peer: Peer) -> usize {
+        self.active.values().filter(|t| t.from == peer).count()
+    }
+}
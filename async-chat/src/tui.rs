@@ -0,0 +1,367 @@
+//! 全屏界面（`client --tui`）：左边滚动的消息，右边房间成员，底下输入行。
+//!
+//! 连接、重连都还是 main 里那一套，这里只管显示和编辑输入。
+
+use std::collections::VecDeque;
+use chrono::Local;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use tokio::io;
+use tokio::sync::mpsc;
+use unicode_width::UnicodeWidthChar;
+
+use async_chat::protocol::{Envelope, Member, ServerFrame};
+use crate::{render, Show};
+
+const SCROLLBACK: usize = 5000; // 最多留多少条消息
+const INPUT_HISTORY: usize = 200; // 上下键能翻到的输入条数
+const SIDEBAR_WIDTH: u16 = 24;
+
+/// 跑界面直到用户退出（Ctrl-C、/quit）或连接循环结束
+pub async fn run(mut shown: mpsc::UnboundedReceiver<Show>, input: mpsc::UnboundedSender<String>) -> io::Result<()> {
+    // 按键和 stdin 一样放在普通线程里阻塞读
+    let (event_tx, mut events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(ev) = event::read() {
+            if event_tx.send(ev).is_err() { break; }
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    let mut app = App::default();
+    let result = loop {
+        if let Err(e) = terminal.draw(|f| app.draw(f)) {
+            break Err(e);
+        }
+        tokio::select! {
+            item = shown.recv() => match item {
+                Some(item) => {
+                    app.show(item);
+                    // 一次收完积压的再重画（重连补历史时一下来很多条）
+                    while let Ok(item) = shown.try_recv() {
+                        app.show(item);
+                    }
+                }
+                None => break Ok(()),
+            },
+            Some(ev) = events.recv() => match ev {
+                Event::Key(key) if key.kind != KeyEventKind::Release => match app.key(key) {
+                    Some(Typed::Line(line)) => {
+                        let _ = input.send(line);
+                    }
+                    Some(Typed::Quit) => break Ok(()),
+                    None => {}
+                },
+                _ => {} // 改窗口大小之类的：下一轮重画就好
+            },
+        }
+    };
+    ratatui::restore();
+    // 被踢、被封之类的最后一句话，退出全屏后还留在终端里
+    if let Some(last) = app.last_note.filter(|_| result.is_ok() && shown.is_closed()) {
+        println!("{last}");
+    }
+    result
+}
+
+/// 按键的结果
+enum Typed {
+    Line(String),
+    Quit,
+}
+
+#[derive(Default)]
+struct App {
+    lines: VecDeque<Line<'static>>, // 滚动区，一条消息一个 Line，显示时再折行
+    scroll: usize,                  // 往上翻了多少行（折行以后的），0 是贴着底部
+    width: usize,                   // 上次画的时候消息区有多宽、多高
+    height: usize,
+    input: String,
+    cursor: usize, // 光标在第几个字符前面
+    sent: VecDeque<String>,
+    browsing: Option<usize>, // 正在看 sent 里的第几条；None 是在编辑新的一行
+    draft: String,           // 开始翻历史前正在编辑的内容
+    room: Option<String>,
+    members: Vec<Member>,
+    last_note: Option<String>,
+}
+
+impl App {
+    fn show(&mut self, item: Show) {
+        let line = match item {
+            Show::Frame(env) => {
+                // 成员列表单独显示，不进滚动区
+                if self.track_members(&env.frame) {
+                    return;
+                }
+                styled(&env)
+            }
+            Show::Raw(line) => Line::raw(line),
+            Show::Note(text) => {
+                self.last_note = Some(text.clone());
+                Line::styled(text, Style::new().fg(Color::Cyan))
+            }
+        };
+        // 往上翻着的时候保持画面不动
+        if self.scroll > 0 {
+            self.scroll += wrap(&line, self.width).len();
+        }
+        if self.lines.len() == SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// 按进出、改名更新成员列表；Members 帧本身返回 true
+    fn track_members(&mut self, frame: &ServerFrame) -> bool {
+        let here = |room: &str| self.room.as_deref() == Some(room);
+        match frame {
+            ServerFrame::Members { room, members } => {
+                self.room = Some(room.clone());
+                self.members = members.clone();
+                return true;
+            }
+            ServerFrame::Join { room, nick } if here(room) && !self.members.iter().any(|m| &m.nick == nick) => {
+                self.members.push(Member { nick: nick.clone(), op: false, away: false });
+                self.members.sort();
+            }
+            ServerFrame::Leave { room, nick } if here(room) => self.members.retain(|m| &m.nick != nick),
+            ServerFrame::Nick { old, new } => {
+                if let Some(m) = self.members.iter_mut().find(|m| &m.nick == old) {
+                    m.nick = new.clone();
+                    self.members.sort();
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<Typed> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if ctrl => return Some(Typed::Quit),
+            KeyCode::Char('d') if ctrl && self.input.is_empty() => return Some(Typed::Quit),
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut self.input);
+                self.cursor = 0;
+                self.browsing = None;
+                self.scroll = 0;
+                if line.trim() == "/quit" {
+                    return Some(Typed::Quit);
+                }
+                if !line.trim().is_empty() && self.sent.back() != Some(&line) {
+                    if self.sent.len() == INPUT_HISTORY {
+                        self.sent.pop_front();
+                    }
+                    self.sent.push_back(line.clone());
+                }
+                // 空行也发：第一行空着表示用默认昵称
+                return Some(Typed::Line(line));
+            }
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.input.chars().count(),
+            KeyCode::Char('u') if ctrl => {
+                let at = self.byte_at(self.cursor);
+                self.input.drain(..at);
+                self.cursor = 0;
+            }
+            KeyCode::Char('k') if ctrl => {
+                let at = self.byte_at(self.cursor);
+                self.input.truncate(at);
+            }
+            KeyCode::Char('w') if ctrl => {
+                // 删掉光标前的一个词（连同后面的空白）
+                let chars: Vec<char> = self.input.chars().take(self.cursor).collect();
+                let blanks = chars.iter().rev().take_while(|c| c.is_whitespace()).count();
+                let word = chars.iter().rev().skip(blanks).take_while(|c| !c.is_whitespace()).count();
+                let from = self.byte_at(self.cursor - blanks - word);
+                let to = self.byte_at(self.cursor);
+                self.input.drain(from..to);
+                self.cursor -= blanks + word;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                let at = self.byte_at(self.cursor);
+                self.input.insert(at, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let at = self.byte_at(self.cursor);
+                self.input.remove(at);
+            }
+            KeyCode::Delete if self.cursor < self.input.chars().count() => {
+                let at = self.byte_at(self.cursor);
+                self.input.remove(at);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.chars().count(),
+            KeyCode::Up => self.browse(true),
+            KeyCode::Down => self.browse(false),
+            KeyCode::PageUp => self.scroll += self.height.saturating_sub(1).max(1),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(self.height.saturating_sub(1).max(1)),
+            _ => {}
+        }
+        None
+    }
+
+    /// 上下键翻之前发过的行；翻回最下面恢复原来没发的草稿
+    fn browse(&mut self, older: bool) {
+        let next = match (self.browsing, older) {
+            (None, true) => self.sent.len().checked_sub(1),
+            (None, false) => return,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) => (i + 1 < self.sent.len()).then_some(i + 1),
+        };
+        if self.browsing.is_none() {
+            self.draft = self.input.clone();
+        }
+        self.input = match next {
+            Some(i) => self.sent[i].clone(),
+            None if self.browsing.is_some() => std::mem::take(&mut self.draft),
+            None => return,
+        };
+        self.browsing = next;
+        self.cursor = self.input.chars().count();
+    }
+
+    /// 第 n 个字符在 input 里的字节位置
+    fn byte_at(&self, n: usize) -> usize {
+        self.input.char_indices().nth(n).map_or(self.input.len(), |(i, _)| i)
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        let [top, bottom] = Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(f.area());
+        let [messages, sidebar] =
+            Layout::horizontal([Constraint::Min(20), Constraint::Length(SIDEBAR_WIDTH)]).areas(top);
+
+        // 消息区：从最新的一条往回折行，够填满（加上往上翻的部分）就停
+        let block = Block::bordered();
+        let inner = block.inner(messages);
+        (self.width, self.height) = (inner.width.max(1) as usize, inner.height as usize);
+        let mut rows = Vec::new();
+        for line in self.lines.iter().rev() {
+            if rows.len() >= self.scroll + self.height {
+                break;
+            }
+            rows.extend(wrap(line, self.width).into_iter().rev());
+        }
+        self.scroll = self.scroll.min(rows.len().saturating_sub(self.height));
+        let visible: Vec<Line> = rows.into_iter().skip(self.scroll).take(self.height).rev().collect();
+        let mut title = match &self.room {
+            Some(room) => format!(" #{room} "),
+            None => " async-chat ".to_string(),
+        };
+        if self.scroll > 0 {
+            title.push_str("(scrolled up; PgDn for newer) ");
+        }
+        f.render_widget(Paragraph::new(visible).block(block.title(title)), messages);
+
+        // 成员列表
+        let names: Vec<Line> = self
+            .members
+            .iter()
+            .map(|m| {
+                let prefix = if m.op { "@" } else { " " };
+                let style = if m.away { Style::new().fg(Color::DarkGray) } else { Style::new().fg(nick_color(&m.nick)) };
+                let away = if m.away { " (away)" } else { "" };
+                Line::styled(format!("{prefix}{}{away}", m.nick), style)
+            })
+            .collect();
+        let block = Block::bordered().title(format!(" {} here ", self.members.len()));
+        f.render_widget(Paragraph::new(names).block(block), sidebar);
+
+        // 输入行：太长就横着滚，保证光标看得见
+        let block = Block::bordered().title(" Enter to send, Ctrl-C or /quit to exit ");
+        let inner = block.inner(bottom);
+        let chars: Vec<char> = self.input.chars().collect();
+        let width = |cs: &[char]| cs.iter().map(|c| c.width().unwrap_or(0)).sum::<usize>();
+        let mut start = 0;
+        while start < self.cursor && width(&chars[start..self.cursor]) >= inner.width as usize {
+            start += 1;
+        }
+        let shown: String = chars[start..].iter().collect();
+        f.render_widget(Paragraph::new(shown).block(block), bottom);
+        let x = inner.x + width(&chars[start..self.cursor]) as u16;
+        f.set_cursor_position(Position::new(x, inner.y));
+    }
+}
+
+/// 给一帧上色：私聊紫色、系统提示黄色、错误红色、进出改名灰色；历史回放整行灰掉
+fn styled(env: &Envelope) -> Line<'static> {
+    let mut spans = Vec::new();
+    if let Some(stamp) = env.stamp {
+        let time = stamp.ts.with_timezone(&Local).format("%H:%M");
+        spans.push(Span::styled(format!("{time} #{} ", stamp.id), Style::new().fg(Color::DarkGray)));
+    }
+    let (frame, history) = match &env.frame {
+        ServerFrame::History { frame } => (&**frame, true),
+        frame => (frame, false),
+    };
+    match frame {
+        // 只会收到当前房间的消息，房间名在标题上，这里不重复
+        ServerFrame::Chat { from, text, .. } => {
+            spans.push(Span::styled(format!("<{from}> "), Style::new().fg(nick_color(from)).add_modifier(Modifier::BOLD)));
+            spans.push(Span::raw(text.clone()));
+        }
+        ServerFrame::Action { from, text, .. } => {
+            spans.push(Span::styled(format!("* {from} {text}"), Style::new().add_modifier(Modifier::ITALIC)));
+        }
+        ServerFrame::Whisper { .. } | ServerFrame::WhisperSent { .. } | ServerFrame::OfflineWhisper { .. } => {
+            spans.push(Span::styled(render(frame), Style::new().fg(Color::Magenta)));
+        }
+        ServerFrame::System { .. } => spans.push(Span::styled(render(frame), Style::new().fg(Color::Yellow))),
+        ServerFrame::Error { .. } => {
+            spans.push(Span::styled(render(frame), Style::new().fg(Color::Red).add_modifier(Modifier::BOLD)));
+        }
+        ServerFrame::Topic { .. } => spans.push(Span::styled(render(frame), Style::new().fg(Color::Green))),
+        _ => spans.push(Span::styled(render(frame), Style::new().fg(Color::DarkGray))),
+    }
+    if history {
+        for span in &mut spans {
+            span.style = span.style.fg(Color::DarkGray);
+        }
+    }
+    Line::from(spans)
+}
+
+/// 同一个人总是同一个颜色
+fn nick_color(nick: &str) -> Color {
+    const COLORS: [Color; 6] =
+        [Color::LightBlue, Color::LightGreen, Color::LightCyan, Color::LightYellow, Color::Blue, Color::Green];
+    let hash = nick.bytes().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    COLORS[hash % COLORS.len()]
+}
+
+/// 按显示宽度折行（按字符折，中文也不会被切坏）
+fn wrap(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if line.width() <= width {
+        return vec![line.clone()];
+    }
+    let mut rows = vec![Line::default()];
+    let mut col = 0;
+    for span in &line.spans {
+        let mut piece = String::new();
+        for c in span.content.chars() {
+            let w = c.width().unwrap_or(0);
+            if col + w > width && col > 0 {
+                let row = rows.last_mut().expect("never empty");
+                row.push_span(Span::styled(std::mem::take(&mut piece), span.style));
+                rows.push(Line::default());
+                col = 0;
+            }
+            piece.push(c);
+            col += w;
+        }
+        if !piece.is_empty() {
+            rows.last_mut().expect("never empty").push_span(Span::styled(piece, span.style));
+        }
+    }
+    rows
+}
@@ -0,0 +1,188 @@
+//! WebSocket 网关：浏览器客户端从另一个端口进来，和 TCP 客户端在同一批房间里。
+//!
+//! 每条 WebSocket 连接在进程内接一根 duplex 管道交给 [`ChatHandle::serve`]，走的还是同一个 handle_conn：
+//! 一个文本帧就是一行输入，服务器写出的每一行变成一个文本帧。心跳换成 WebSocket 自己的 Ping/Pong，
+//! 浏览器会自动回，页面上不用管。`/` 上给一个测试用的小页面，`/ws` 是 WebSocket 入口
+
+use std::net::SocketAddr;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::protocol::{ClientFrame, Envelope, ServerFrame, PROTO_JSON};
+use crate::ChatHandle;
+
+/// 测试页面
+const PAGE: &str = include_str!("../static/chat.html");
+/// HTTP 请求头最多读多少字节、等多久
+const MAX_HEAD: usize = 8 * 1024;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// 网关和 handle_conn 之间的管道缓冲
+const PIPE_BUFFER: usize = 64 * 1024;
+
+impl ChatHandle {
+    /// 在监听器上接 WebSocket 连接（和测试页面），直到关停。连接数上限、封禁和 TCP 共用
+    pub async fn run_websocket(&self, listener: TcpListener) -> io::Result<()> {
+        let mut stop = self.inner.shutdown.subscribe();
+        loop {
+            let (socket, peer) = tokio::select! {
+                res = listener.accept() => res?,
+                _ = stop.wait_for(|stop| *stop) => return Ok(()),
+            };
+            let handle = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle.accept_http(socket, peer).await {
+                    eprintln!("! WebSocket {peer}: {e}");
+                }
+            });
+        }
+    }
+
+    /// 读 HTTP 请求头：升级请求转成 WebSocket，`/` 给页面，别的 404
+    async fn accept_http(&self, mut socket: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        let head = match timeout(HEAD_TIMEOUT, read_head(&mut socket)).await {
+            Ok(head) => head?,
+            Err(_) => return Ok(()), // 半天不说话的直接关
+        };
+        let Some(request) = Request::parse(&head) else {
+            return respond(&mut socket, "400 Bad Request", "text/plain", "Bad request\n").await;
+        };
+        match (request.method, request.path, request.websocket_key) {
+            ("GET", "/ws", Some(key)) => {
+                let accept = derive_accept_key(key.as_bytes());
+                let reply = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                     Sec-WebSocket-Accept: {accept}\r\n\r\n"
+                );
+                socket.write_all(reply.as_bytes()).await?;
+                // 超长的帧交给 handle_conn 按超长行处理（有提示）；留点余量，再长的直接断开，免得网关先把内存吃光
+                let limit = self.inner.config.max_line_len * 4;
+                let config = WebSocketConfig::default().max_message_size(Some(limit)).max_frame_size(Some(limit));
+                let ws = WebSocketStream::from_raw_socket(socket, Role::Server, Some(config)).await;
+                self.bridge(ws, peer).await;
+                Ok(())
+            }
+            ("GET", "/ws", None) => respond(&mut socket, "426 Upgrade Required", "text/plain", "WebSocket only\n").await,
+            ("GET", "/" | "/index.html", _) => respond(&mut socket, "200 OK", "text/html; charset=utf-8", PAGE).await,
+            ("GET", ..) => respond(&mut socket, "404 Not Found", "text/plain", "Not found\n").await,
+            _ => respond(&mut socket, "405 Method Not Allowed", "text/plain", "GET only\n").await,
+        }
+    }
+
+    /// 在 WebSocket 和 handle_conn 之间来回搬：帧 -> 行，行 -> 帧
+    async fn bridge(&self, ws: WebSocketStream<TcpStream>, peer: SocketAddr) {
+        let (ours, theirs) = io::duplex(PIPE_BUFFER);
+        self.serve(theirs, peer).await;
+        let (reader, mut writer) = io::split(ours);
+        let mut lines = BufReader::new(reader).lines();
+        let (mut sink, mut stream) = ws.split();
+        // 跟着 handle_conn 的协商走：只有第一帧是 `/proto json` 才算 JSON 模式，心跳帧的写法不一样
+        let mut json = false;
+        let mut first = true;
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Ok(Some(line)) = line else {
+                        // 服务器那边结束了（退出、被踢、关停）
+                        let _ = sink.send(Message::Close(None)).await;
+                        break;
+                    };
+                    let msg = if is_ping(&line, json) { Message::Ping(Default::default()) } else { Message::text(line) };
+                    if sink.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                msg = stream.next() => {
+                    let line = match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            // 一帧一行：帧里夹的换行当空格，不能拆成好几条
+                            let line = text.as_str().replace(['\r', '\n'], " ");
+                            if first && line.trim() == PROTO_JSON {
+                                json = true;
+                            }
+                            first = false;
+                            line
+                        }
+                        Some(Ok(Message::Pong(_))) if json => serde_json::to_string(&ClientFrame::Pong).expect("frame serializes"),
+                        Some(Ok(Message::Pong(_))) => "PONG".to_string(),
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue, // 二进制帧不认；对方的 Ping 由 tungstenite 自动回
+                    };
+                    if writer.write_all(format!("{line}\n").as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        // 丢掉管道，handle_conn 读到 EOF 自己收尾
+    }
+}
+
+/// 服务器写出的这一行是不是心跳
+fn is_ping(line: &str, json: bool) -> bool {
+    if json {
+        matches!(serde_json::from_str::<Envelope>(line), Ok(Envelope { frame: ServerFrame::Ping, .. }))
+    } else {
+        line == "PING"
+    }
+}
+
+/// 读到空行为止（不多读，后面就是 WebSocket 帧）
+async fn read_head(socket: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD || socket.read(&mut byte).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete request head"));
+        }
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "request head is not UTF-8"))
+}
+
+/// 只认用得到的几样
+struct Request<'a> {
+    method: &'a str,
+    path: &'a str,
+    websocket_key: Option<&'a str>, // 升级请求才有
+}
+
+impl<'a> Request<'a> {
+    fn parse(head: &'a str) -> Option<Self> {
+        let mut lines = head.lines();
+        let mut parts = lines.next()?.split_whitespace();
+        let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+        if !version.starts_with("HTTP/1.") {
+            return None;
+        }
+        let path = target.split('?').next().unwrap_or(target);
+
+        let mut upgrade = false;
+        let mut key = None;
+        for line in lines.take_while(|l| !l.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("upgrade") {
+                upgrade = value.eq_ignore_ascii_case("websocket");
+            } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value);
+            }
+        }
+        Some(Request { method, path, websocket_key: key.filter(|_| upgrade) })
+    }
+}
+
+/// 普通 HTTP 回应，回完就关
+async fn respond(socket: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    let reply = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(reply.as_bytes()).await?;
+    socket.shutdown().await
+}
@@ -0,0 +1,248 @@
+//! 集成测试的小工具：在临时目录里起一个服务器（随机端口），模拟多个客户端，按 JSON 帧断言收到了什么
+
+// 每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use async_chat::protocol::{ClientFrame, Envelope, ServerFrame, PROTO_JSON};
+use async_chat::{ChatHandle, ChatServer, Config};
+use futures_util::SinkExt;
+use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{client_async, WebSocketStream};
+
+/// 等一帧最多等多久；暂停时间的测试里是虚拟时间，等更久的用 recv_within
+pub const WAIT: Duration = Duration::from_secs(5);
+/// assert_quiet 看多久没动静
+const QUIET: Duration = Duration::from_millis(200);
+
+static NEXT: AtomicU32 = AtomicU32::new(1);
+
+/// 测试用服务器：临时数据目录 + 127.0.0.1 上的随机端口
+pub struct TestServer {
+    pub handle: ChatHandle,
+    pub addr: SocketAddr,
+    pub ws_addr: SocketAddr,  // WebSocket 网关
+    pub irc_addr: SocketAddr, // IRC 网关
+    dir: PathBuf,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::with(|_| {}).await
+    }
+
+    /// 先改配置再启动；机器人默认关掉，免得掺进断言
+    pub async fn with(tweak: impl FnOnce(&mut Config)) -> Self {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("async-chat-test-{}-{n}", std::process::id()));
+        let mut config = Config::default();
+        tweak(&mut config);
+        let handle = ChatServer::new(config).data_dir(&dir).bots(false).start().await.expect("server starts");
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().unwrap();
+        let runner = handle.clone();
+        tokio::spawn(async move { runner.run(listener).await });
+        let ws_listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let ws_addr = ws_listener.local_addr().unwrap();
+        let runner = handle.clone();
+        tokio::spawn(async move { runner.run_websocket(ws_listener).await });
+        let irc_listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let irc_addr = irc_listener.local_addr().unwrap();
+        let runner = handle.clone();
+        tokio::spawn(async move { runner.run_irc(irc_listener).await });
+        TestServer { handle, addr, ws_addr, irc_addr, dir }
+    }
+
+    /// TCP 连上并起好名字，等到进了大厅（收到成员列表）才返回
+    pub async fn connect(&self, nick: &str) -> TestClient {
+        let mut client = self.open(nick, None).await;
+        client.until_joined().await;
+        client
+    }
+
+    /// 只连上、发完首行就返回，进大厅时的历史、成员列表留给测试自己读。给了 since 就先 `/since`（模拟重连）
+    pub async fn open(&self, nick: &str, since: Option<u64>) -> TestClient {
+        let stream = TcpStream::connect(self.addr).await.expect("connect");
+        let mut first: Vec<String> = since.map(|id| format!("/since {id}")).into_iter().collect();
+        first.push(format!("/nick {nick}"));
+        TestClient::open(Box::new(stream), &first).await
+    }
+
+    /// 进程内连接（duplex，不走网络），暂停时间的测试用这个：虚拟时钟跳过去的时候不会有数据还在路上
+    pub async fn connect_in_memory(&self, nick: &str) -> TestClient {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let peer = SocketAddr::from(([10, 0, (n >> 8) as u8, n as u8], 9000));
+        let mut client = self.open_in_memory(peer, &[format!("/nick {nick}")]).await;
+        client.until_joined().await;
+        client
+    }
+
+    /// 进程内连接，地址由测试指定：模拟网关转进来的连接和别的连接是同一个 ip:port
+    pub async fn open_in_memory(&self, peer: SocketAddr, first: &[String]) -> TestClient {
+        let (client, server) = io::duplex(64 * 1024);
+        self.handle.serve(server, peer).await;
+        TestClient::open(Box::new(client), first).await
+    }
+
+    /// 纯文本模式的连接，只发首行，不等任何东西
+    pub async fn connect_text(&self, first: &str) -> (Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>) {
+        let stream = TcpStream::connect(self.addr).await.expect("connect");
+        let (r, mut w) = io::split(stream);
+        w.write_all(format!("{first}\n").as_bytes()).await.unwrap();
+        (BufReader::new(r).lines(), w)
+    }
+
+    /// 从 WebSocket 网关连进来，发完首帧就返回
+    pub async fn connect_websocket(&self, first: &str) -> WebSocketStream<TcpStream> {
+        let stream = TcpStream::connect(self.ws_addr).await.expect("connect");
+        let (mut ws, _) = client_async(format!("ws://{}/ws", self.ws_addr), stream).await.expect("handshake");
+        ws.send(Message::text(first)).await.unwrap();
+        ws
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// 模拟的 JSON 客户端：自动回 PONG（除非 ignore_pings），其余帧交给测试断言
+pub struct TestClient {
+    lines: Lines<BufReader<ReadHalf<Box<dyn Stream>>>>,
+    writer: WriteHalf<Box<dyn Stream>>,
+    answer_pings: bool,
+}
+
+impl TestClient {
+    async fn open(stream: Box<dyn Stream>, first: &[String]) -> Self {
+        let (r, mut writer) = io::split(stream);
+        writer.write_all(format!("{PROTO_JSON}\n").as_bytes()).await.unwrap();
+        let mut client = TestClient { lines: BufReader::new(r).lines(), writer, answer_pings: true };
+        for line in first {
+            client.send(line).await;
+        }
+        client
+    }
+
+    /// 进房间时最后发的是成员列表，读到它就算进来了
+    pub async fn until_joined(&mut self) {
+        self.expect(|f| matches!(f, ServerFrame::Members { .. }).then_some(())).await;
+    }
+
+    /// 发一行（聊天或命令）
+    pub async fn send(&mut self, text: &str) {
+        self.write(&ClientFrame::Line { text: text.to_string() }).await.expect("write");
+    }
+
+    /// 发一个原始的 JSON 帧（传文件之类）
+    pub async fn send_frame(&mut self, frame: ClientFrame) {
+        self.write(&frame).await.expect("write");
+    }
+
+    async fn write(&mut self, frame: &ClientFrame) -> io::Result<()> {
+        let mut line = serde_json::to_string(frame).unwrap();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await
+    }
+
+    /// 之后不再回 PONG，模拟卡死的客户端
+    pub fn ignore_pings(&mut self) {
+        self.answer_pings = false;
+    }
+
+    /// 下一帧（跳过 PING）；连接关了返回 None，等太久直接失败
+    pub async fn recv_within(&mut self, limit: Duration) -> Option<Envelope> {
+        loop {
+            let line = timeout(limit, self.lines.next_line()).await.expect("timed out waiting for a frame");
+            let line = line.ok().flatten()?;
+            let env: Envelope = serde_json::from_str(&line).unwrap_or_else(|e| panic!("bad frame {line:?}: {e}"));
+            if let ServerFrame::Ping = env.frame {
+                // 服务器可能已经不读了（正在断开），回不回得去无所谓
+                if self.answer_pings {
+                    let _ = self.write(&ClientFrame::Pong).await;
+                }
+                continue;
+            }
+            return Some(env);
+        }
+    }
+
+    pub async fn recv(&mut self) -> Envelope {
+        self.recv_within(WAIT).await.expect("connection closed")
+    }
+
+    /// 一直读到 pick 认出来的那一帧，跳过其他的
+    pub async fn expect<T>(&mut self, mut pick: impl FnMut(&ServerFrame) -> Option<T>) -> T {
+        loop {
+            let env = self.recv().await;
+            if let Some(found) = pick(&env.frame) {
+                return found;
+            }
+        }
+    }
+
+    /// 等一条包含 needle 的系统提示或错误，返回全文
+    pub async fn expect_notice(&mut self, needle: &str) -> String {
+        self.expect_notice_within(needle, WAIT).await
+    }
+
+    /// 同上，每一帧最多等 limit（暂停时间的测试里等超时用）
+    pub async fn expect_notice_within(&mut self, needle: &str, limit: Duration) -> String {
+        loop {
+            match self.recv_within(limit).await.expect("connection closed").frame {
+                ServerFrame::System { text } | ServerFrame::Error { text } if text.contains(needle) => return text,
+                _ => {}
+            }
+        }
+    }
+
+    /// 等一条错误（下一条非 PING 的帧就得是）
+    pub async fn expect_error(&mut self) -> String {
+        match self.recv().await.frame {
+            ServerFrame::Error { text } => text,
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    /// 一小会儿内什么都没收到（PING 除外）
+    pub async fn assert_quiet(&mut self) {
+        let got = timeout(QUIET, self.recv_within(WAIT)).await;
+        if let Ok(Some(env)) = got {
+            panic!("expected nothing, got {:?}", env.frame);
+        }
+    }
+
+    /// 服务器关掉了连接；之前的帧都跳过
+    pub async fn expect_closed(&mut self) {
+        self.expect_closed_within(WAIT).await;
+    }
+
+    pub async fn expect_closed_within(&mut self, limit: Duration) {
+        while self.recv_within(limit).await.is_some() {}
+    }
+
+    /// 干等一段时间（照样回 PONG），期间收到的帧都丢掉
+    pub async fn idle(&mut self, period: Duration) {
+        let _ = timeout(period, async { while self.recv_within(period * 2).await.is_some() {} }).await;
+    }
+
+    /// 把已经到了的帧都读掉
+    pub async fn drain(&mut self) {
+        while let Ok(Some(_)) = timeout(QUIET, self.recv_within(WAIT)).await {}
+    }
+
+    /// 断开（关掉写的一半，服务器读到 EOF）
+    pub async fn quit(mut self) {
+        let _ = self.writer.shutdown().await;
+        sleep(Duration::from_millis(50)).await;
+    }
+}
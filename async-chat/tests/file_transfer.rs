@@ -0,0 +1,234 @@
+//! 传文件：服务器只转发报价、接收、数据块和确认，检查大小和顺序，断线时通知另一方
+
+mod common;
+
+use async_chat::protocol::{ClientFrame, ServerFrame};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use common::{TestClient, TestServer};
+
+/// "hello" 的 SHA-256
+const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+fn offer(to: &str, size: u64) -> ClientFrame {
+    ClientFrame::FileOffer { to: to.to_string(), name: "hello.txt".to_string(), size }
+}
+
+/// alice 报价、bob 接收，返回传输编号
+async fn accepted(alice: &mut TestClient, bob: &mut TestClient) -> u64 {
+    alice.send_frame(offer("bob", 5)).await;
+    let transfer = bob.expect(|f| match f {
+        ServerFrame::FileOffer { transfer, .. } => Some(*transfer),
+        _ => None,
+    });
+    let transfer = transfer.await;
+    bob.send_frame(ClientFrame::FileAccept { transfer }).await;
+    alice.expect(|f| matches!(f, ServerFrame::FileAccepted { .. }).then_some(())).await;
+    transfer
+}
+
+fn cancelled(frame: &ServerFrame) -> Option<(u64, String, String)> {
+    match frame {
+        ServerFrame::FileCancelled { transfer, by, reason } => Some((*transfer, by.clone(), reason.clone())),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn a_file_is_relayed_chunk_by_chunk() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    alice.send_frame(offer("bob", 5)).await;
+    let got = bob.expect(|f| match f {
+        ServerFrame::FileOffer { transfer, from, name, size } => Some((*transfer, from.clone(), name.clone(), *size)),
+        _ => None,
+    });
+    let (transfer, from, name, size) = got.await;
+    assert_eq!((from.as_str(), name.as_str(), size), ("alice", "hello.txt", 5));
+    let offered = alice.expect(|f| match f {
+        ServerFrame::FileOffered { transfer, to, .. } => Some((*transfer, to.clone())),
+        _ => None,
+    });
+    assert_eq!(offered.await, (transfer, "bob".to_string()));
+
+    bob.send_frame(ClientFrame::FileAccept { transfer }).await;
+    let (by, chunk, window) = alice
+        .expect(|f| match f {
+            ServerFrame::FileAccepted { by, chunk, window, .. } => Some((by.clone(), *chunk, *window)),
+            _ => None,
+        })
+        .await;
+    assert_eq!(by, "bob");
+    assert!(chunk >= 5 && window >= 1, "chunk {chunk}, window {window}");
+
+    alice.send_frame(ClientFrame::FileChunk { transfer, seq: 0, data: BASE64.encode("hello") }).await;
+    let data = bob.expect(|f| match f {
+        ServerFrame::FileChunk { seq: 0, data, .. } => Some(data.clone()),
+        _ => None,
+    });
+    assert_eq!(BASE64.decode(data.await).unwrap(), b"hello");
+    bob.send_frame(ClientFrame::FileAck { transfer, seq: 0 }).await;
+    alice.expect(|f| matches!(f, ServerFrame::FileAck { seq: 0, .. }).then_some(())).await;
+
+    // 校验和是发送方边发边算的，跟着 FileDone 转给接收方
+    alice.send_frame(ClientFrame::FileDone { transfer, sha256: HELLO_SHA256.to_uppercase() }).await;
+    let sha256 = bob.expect(|f| match f {
+        ServerFrame::FileDone { sha256, .. } => Some(sha256.clone()),
+        _ => None,
+    });
+    assert_eq!(sha256.await, HELLO_SHA256);
+    // 接收方校验通过后回一个 FileDone，发送方才知道存好了
+    bob.send_frame(ClientFrame::FileDone { transfer, sha256: String::new() }).await;
+    alice.expect(|f| matches!(f, ServerFrame::FileDone { .. }).then_some(())).await;
+    // 传完就没了，再取消找不到
+    bob.send_frame(ClientFrame::FileCancel { transfer, reason: String::new() }).await;
+    assert_eq!(bob.expect_error().await, format!("No transfer #{transfer}"));
+}
+
+#[tokio::test]
+async fn offers_over_the_size_limit_are_refused() {
+    let server = TestServer::with(|c| c.max_file_size = 4).await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    alice.send_frame(offer("bob", 5)).await;
+    assert_eq!(alice.expect_error().await, "File is too large (5 bytes, limit is 4)");
+    bob.assert_quiet().await;
+}
+
+#[tokio::test]
+async fn transfers_can_be_turned_off() {
+    let server = TestServer::with(|c| c.max_file_size = 0).await;
+    let mut alice = server.connect("alice").await;
+    let _bob = server.connect("bob").await;
+    alice.drain().await;
+
+    alice.send_frame(offer("bob", 5)).await;
+    assert_eq!(alice.expect_error().await, "File transfer is disabled on this server");
+}
+
+#[tokio::test]
+async fn bad_offers_are_refused() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let (_lines, _w) = server.connect_text("/nick tex").await;
+    alice.drain().await;
+
+    alice.send_frame(offer("alice", 5)).await;
+    assert_eq!(alice.expect_error().await, "You can't send files to yourself");
+    alice.send_frame(offer("nobody", 5)).await;
+    assert_eq!(alice.expect_error().await, "User 'nobody' not found");
+    // 纯文本客户端收不了
+    alice.send_frame(offer("tex", 5)).await;
+    assert_eq!(alice.expect_error().await, "tex's client can't receive files");
+    let frame = ClientFrame::FileOffer { to: "tex".into(), name: "..".into(), size: 5 };
+    alice.send_frame(frame).await;
+    assert_eq!(alice.expect_error().await, "Invalid file name '..'");
+}
+
+#[tokio::test]
+async fn rejecting_tells_the_sender() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    alice.send_frame(offer("bob", 5)).await;
+    let transfer = bob.expect(|f| match f {
+        ServerFrame::FileOffer { transfer, .. } => Some(*transfer),
+        _ => None,
+    });
+    let transfer = transfer.await;
+    bob.send_frame(ClientFrame::FileCancel { transfer, reason: "rejected".to_string() }).await;
+    assert_eq!(alice.expect(cancelled).await, (transfer, "bob".to_string(), "rejected".to_string()));
+    assert_eq!(bob.expect(cancelled).await, (transfer, "bob".to_string(), "rejected".to_string()));
+}
+
+#[tokio::test]
+async fn chunks_out_of_order_or_too_large_cancel() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    let transfer = accepted(&mut alice, &mut bob).await;
+    alice.send_frame(ClientFrame::FileChunk { transfer, seq: 1, data: BASE64.encode("hello") }).await;
+    let (_, by, reason) = bob.expect(cancelled).await;
+    assert_eq!((by.as_str(), reason.as_str()), ("server", "chunk 1 out of order (expected 0)"));
+    alice.expect(cancelled).await;
+
+    // 比报的大小多
+    let transfer = accepted(&mut alice, &mut bob).await;
+    alice.send_frame(ClientFrame::FileChunk { transfer, seq: 0, data: BASE64.encode("hello!") }).await;
+    assert_eq!(bob.expect(cancelled).await.2, "bad chunk 0");
+}
+
+#[tokio::test]
+async fn finishing_without_a_checksum_cancels() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    let transfer = accepted(&mut alice, &mut bob).await;
+    alice.send_frame(ClientFrame::FileChunk { transfer, seq: 0, data: BASE64.encode("hello") }).await;
+    alice.send_frame(ClientFrame::FileDone { transfer, sha256: "hello".to_string() }).await;
+    let (_, by, reason) = bob.expect(cancelled).await;
+    assert_eq!((by.as_str(), reason.as_str()), ("server", "bad checksum: expected 64 hex digits of SHA-256"));
+    alice.expect(cancelled).await;
+}
+
+#[tokio::test]
+async fn disconnecting_cancels_the_transfer() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    let transfer = accepted(&mut alice, &mut bob).await;
+    bob.quit().await;
+    assert_eq!(alice.expect(cancelled).await, (transfer, "bob".to_string(), "disconnected".to_string()));
+}
+
+#[tokio::test]
+async fn only_new_acks_reach_the_sender() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    let transfer = accepted(&mut alice, &mut bob).await;
+    alice.send_frame(ClientFrame::FileChunk { transfer, seq: 0, data: BASE64.encode("hel") }).await;
+    bob.expect(|f| matches!(f, ServerFrame::FileChunk { seq: 0, .. }).then_some(())).await;
+    // 还没转过的块不能确认
+    bob.send_frame(ClientFrame::FileAck { transfer, seq: 1 }).await;
+    bob.send_frame(ClientFrame::FileAck { transfer, seq: 0 }).await;
+    alice.expect(|f| matches!(f, ServerFrame::FileAck { seq: 0, .. }).then_some(())).await;
+    // 重复的不再转
+    bob.send_frame(ClientFrame::FileAck { transfer, seq: 0 }).await;
+    alice.assert_quiet().await;
+}
+
+#[tokio::test]
+async fn unexpected_file_frames_count_as_flooding() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    let transfer = accepted(&mut alice, &mut bob).await;
+    for _ in 0..20 {
+        bob.send_frame(ClientFrame::FileAck { transfer, seq: 0 }).await;
+    }
+    assert!(bob.expect_error().await.starts_with("You are sending too fast"));
+    // 一直刷就被断开；在那之前发送方一个确认也没收到
+    let first = alice.expect(|f| match f {
+        ServerFrame::FileAck { .. } => Some("ack".to_string()),
+        ServerFrame::FileCancelled { reason, .. } => Some(reason.clone()),
+        _ => None,
+    });
+    assert_eq!(first.await, "disconnected");
+}
@@ -0,0 +1,491 @@
+//! 一个连接从握手到断开的行为：进出广播、昵称冲突、私聊路由、历史回放、空闲超时和心跳
+
+mod common;
+
+use async_chat::protocol::{Member, ServerFrame};
+use common::TestServer;
+use tokio::time::{Duration, Instant};
+
+fn chat_text(frame: &ServerFrame) -> Option<String> {
+    match frame {
+        ServerFrame::Chat { text, .. } => Some(text.clone()),
+        _ => None,
+    }
+}
+
+// === 进出 ===
+
+#[tokio::test]
+async fn join_and_leave_are_broadcast() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let bob = server.connect("bob").await;
+
+    let joined = alice.expect(|f| match f {
+        ServerFrame::Join { room, nick } => Some((room.clone(), nick.clone())),
+        _ => None,
+    });
+    assert_eq!(joined.await, ("lobby".to_string(), "bob".to_string()));
+
+    bob.quit().await;
+    let left = alice.expect(|f| match f {
+        ServerFrame::Leave { room, nick } => Some((room.clone(), nick.clone())),
+        _ => None,
+    });
+    assert_eq!(left.await, ("lobby".to_string(), "bob".to_string()));
+}
+
+#[tokio::test]
+async fn newcomer_gets_the_member_list() {
+    let server = TestServer::start().await;
+    let _alice = server.connect("alice").await;
+    let _bob = server.connect("bob").await;
+
+    let mut carol = server.open("carol", None).await;
+    let members = carol.expect(|f| match f {
+        ServerFrame::Members { room, members } if room == "lobby" => Some(members.clone()),
+        _ => None,
+    });
+    let nicks: Vec<String> = members.await.into_iter().map(|m| m.nick).collect();
+    assert_eq!(nicks, ["alice", "bob", "carol"]);
+}
+
+#[tokio::test]
+async fn rooms_are_isolated() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    bob.send("/join dev").await;
+    let members = bob.expect(|f| match f {
+        ServerFrame::Members { room, members } if room == "dev" => Some(members.clone()),
+        _ => None,
+    });
+    assert_eq!(members.await, [Member { nick: "bob".to_string(), op: true, away: false }]);
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "bob").then_some(())).await;
+
+    bob.send("only for dev").await;
+    alice.assert_quiet().await;
+
+    alice.send("only for the lobby").await;
+    bob.assert_quiet().await;
+}
+
+#[tokio::test]
+async fn connections_from_the_same_address_stay_apart() {
+    let server = TestServer::start().await;
+    // 网关转进来的连接可能和 TCP 连接是同一个 ip:port
+    let addr = "10.9.9.9:9000".parse().unwrap();
+    let mut alice = server.open_in_memory(addr, &["/nick alice".to_string()]).await;
+    alice.until_joined().await;
+    let mut bob = server.open_in_memory(addr, &["/nick bob".to_string()]).await;
+    bob.until_joined().await;
+    let mut anon = server.open_in_memory(addr, &["hello".to_string()]).await;
+    anon.until_joined().await;
+    let mut other = server.open_in_memory(addr, &["hi".to_string()]).await;
+    other.until_joined().await;
+
+    let mut nicks: Vec<String> = server.handle.users().await.into_iter().map(|u| u.nick).collect();
+    nicks.sort();
+    assert_eq!(nicks.len(), 4, "{nicks:?}");
+    nicks.dedup();
+    assert_eq!(nicks.len(), 4, "default names collide: {nicks:?}");
+
+    alice.drain().await;
+    alice.send("/w bob psst").await;
+    let got = bob.expect(|f| match f {
+        ServerFrame::Whisper { from, text } => Some((from.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("alice".to_string(), "psst".to_string()));
+
+    bob.quit().await;
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "bob").then_some(())).await;
+    alice.send("still here").await;
+    let heard = anon.expect(|f| match f {
+        ServerFrame::Chat { from, text, .. } if from == "alice" => Some(text.clone()),
+        _ => None,
+    });
+    assert_eq!(heard.await, "still here");
+    assert_eq!(server.handle.users().await.len(), 3);
+}
+
+// === 昵称 ===
+
+#[tokio::test]
+async fn taken_nick_on_connect_falls_back_to_the_address() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.drain().await;
+
+    // 第二个 alice 进得来，但用的是地址名，并收到提示
+    let _other = server.connect("alice").await;
+    let (room, nick) = alice
+        .expect(|f| match f {
+            ServerFrame::Join { room, nick } => Some((room.clone(), nick.clone())),
+            _ => None,
+        })
+        .await;
+    assert_eq!(room, "lobby");
+    assert!(nick.starts_with("127.0.0.1:"), "fell back to {nick}");
+    let users = server.handle.users().await;
+    assert_eq!(users.iter().filter(|u| u.nick == "alice").count(), 1);
+}
+
+#[tokio::test]
+async fn taken_nick_on_connect_is_reported() {
+    let server = TestServer::start().await;
+    let _alice = server.connect("alice").await;
+    let (mut lines, _w) = server.connect_text("/nick alice").await;
+    let mut seen = Vec::new();
+    while let Ok(Ok(Some(line))) = tokio::time::timeout(Duration::from_secs(2), lines.next_line()).await {
+        if line.contains("is taken") {
+            assert!(line.contains("Nick 'alice' is taken. You are 127.0.0.1:"), "{line}");
+            return;
+        }
+        seen.push(line);
+    }
+    panic!("no collision notice in {seen:?}");
+}
+
+#[tokio::test]
+async fn nick_change_collision_is_rejected() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.drain().await;
+
+    bob.send("/nick alice").await;
+    assert_eq!(bob.expect_error().await, "Nick 'alice' is taken");
+    alice.assert_quiet().await;
+
+    bob.send("/nick robert").await;
+    let renamed = alice.expect(|f| match f {
+        ServerFrame::Nick { old, new } => Some((old.clone(), new.clone())),
+        _ => None,
+    });
+    assert_eq!(renamed.await, ("bob".to_string(), "robert".to_string()));
+}
+
+// === 私聊 ===
+
+#[tokio::test]
+async fn whispers_reach_only_the_target() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    let mut carol = server.connect("carol").await;
+    alice.drain().await;
+    bob.drain().await;
+
+    alice.send("/w bob psst").await;
+    let got = bob.expect(|f| match f {
+        ServerFrame::Whisper { from, text } => Some((from.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("alice".to_string(), "psst".to_string()));
+    let echo = alice.expect(|f| match f {
+        ServerFrame::WhisperSent { to, text } => Some((to.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(echo.await, ("bob".to_string(), "psst".to_string()));
+    carol.assert_quiet().await;
+}
+
+#[tokio::test]
+async fn whisper_to_a_stranger_is_an_error() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.drain().await;
+    alice.send("/w nobody hello").await;
+    assert_eq!(alice.expect_error().await, "User 'nobody' not found");
+}
+
+#[tokio::test]
+async fn whispers_to_a_stalled_client_are_reported_undelivered() {
+    let server = TestServer::with(|c| {
+        c.outbound_queue = 4;
+        c.history_cap = 2;
+        c.mailbox_size = 2;
+        c.rate_limit_per_sec = 1000;
+        c.rate_limit_burst = 1000;
+    })
+    .await;
+    let mut alice = server.connect("alice").await;
+    let _bob = server.connect_in_memory("bob").await; // 不读，管道和写队列迟早塞满
+    alice.drain().await;
+
+    let text = "x".repeat(3000);
+    for _ in 0..100 {
+        alice.send(&format!("/w bob {text}")).await;
+        let reply = alice.expect(|f| match f {
+            ServerFrame::WhisperSent { .. } => Some(None),
+            ServerFrame::Error { text } => Some(Some(text.clone())),
+            _ => None,
+        });
+        if let Some(error) = reply.await {
+            assert_eq!(error, "bob is not reading right now; your whisper was not delivered");
+            return;
+        }
+    }
+    panic!("every whisper was echoed as sent");
+}
+
+#[tokio::test]
+async fn offline_whispers_are_delivered_on_login() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let bob = server.connect("bob").await;
+    bob.quit().await;
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "bob").then_some(())).await;
+
+    alice.send("/w bob see you later").await;
+    let queued = alice.expect(|f| match f {
+        ServerFrame::System { text } if text.contains("queued") => Some(text.clone()),
+        _ => None,
+    });
+    assert_eq!(queued.await, "bob is offline; message queued (1/20)");
+
+    // 时间字段和信封里的 ts 不能撞名，否则 JSON 客户端解不出这一帧
+    let mut bob = server.open("bob", None).await;
+    let got = bob.expect(|f| match f {
+        ServerFrame::OfflineWhisper { from, text, sent_at } => Some((from.clone(), text.clone(), *sent_at)),
+        _ => None,
+    });
+    let (from, text, sent_at) = got.await;
+    assert_eq!((from.as_str(), text.as_str()), ("alice", "see you later"));
+    assert!(sent_at > 0);
+}
+
+// === 历史 ===
+
+#[tokio::test]
+async fn history_is_replayed_in_order() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    for text in ["one", "two", "three"] {
+        alice.send(text).await;
+    }
+    alice.quit().await;
+
+    let mut bob = server.open("bob", None).await;
+    let mut replayed = Vec::new();
+    loop {
+        let env = bob.recv().await;
+        match env.frame {
+            ServerFrame::History { frame } => {
+                if let Some(text) = chat_text(&frame) {
+                    replayed.push((env.stamp.expect("history has ids").id, text));
+                }
+            }
+            ServerFrame::Members { .. } => break,
+            _ => {}
+        }
+    }
+    let texts: Vec<&str> = replayed.iter().map(|(_, t)| t.as_str()).collect();
+    assert_eq!(texts, ["one", "two", "three"]);
+    assert!(replayed.windows(2).all(|w| w[0].0 < w[1].0), "ids increase: {replayed:?}");
+}
+
+#[tokio::test]
+async fn reconnect_with_since_replays_only_newer_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    alice.send("before").await;
+    let seen = bob.recv().await;
+    assert_eq!(chat_text(&seen.frame).as_deref(), Some("before"));
+    bob.quit().await;
+    alice.send("while you were gone").await;
+
+    let mut bob = server.open("bob", Some(seen.stamp.unwrap().id)).await;
+    let mut replayed = Vec::new();
+    loop {
+        match bob.recv().await.frame {
+            ServerFrame::History { frame } => replayed.extend(chat_text(&frame)),
+            ServerFrame::Members { .. } => break,
+            _ => {}
+        }
+    }
+    assert_eq!(replayed, ["while you were gone"]);
+}
+
+// === 话题 ===
+
+#[tokio::test]
+async fn any_member_can_set_the_topic() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.send("/join den").await;
+    let mut bob = server.connect("bob").await;
+    bob.send("/join den").await;
+    alice.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "bob").then_some(())).await;
+
+    // alice 先进来是管理员，bob 不是
+    bob.send("/topic snacks at five").await;
+    let got = alice.expect(|f| match f {
+        ServerFrame::Topic { by, text, .. } => Some((by.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("bob".to_string(), "snacks at five".to_string()));
+    bob.send("/topic").await;
+    bob.expect_notice("Topic for #den: snacks at five (set by bob)").await;
+}
+
+// === 管理 ===
+
+#[tokio::test]
+async fn oversized_mute_and_ban_durations_are_refused() {
+    let server = TestServer::with(|c| c.operators.push("root".to_string())).await;
+    let mut root = server.connect("root").await;
+    root.send("/register root hunter22").await;
+    root.expect_notice("Registered and logged in as root").await;
+    root.send("/join den").await;
+    let mut bob = server.connect("bob").await;
+    bob.send("/join den").await;
+    root.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "bob").then_some(())).await;
+
+    root.send("/mute bob 200000000000000d").await;
+    assert_eq!(root.expect_error().await, "Duration 200000000000000d is too long (max 365d)");
+    root.send("/ban bob 366d").await;
+    assert_eq!(root.expect_error().await, "Duration 366d is too long (max 365d)");
+
+    // 连接还在，上限以内的照常生效
+    root.send("/mute bob 365d").await;
+    root.expect_notice("Muted bob for 365d").await;
+    bob.expect_notice("You were muted by root for 365d").await;
+}
+
+// === 纯文本客户端 ===
+
+#[tokio::test]
+async fn plain_text_clients_get_rendered_lines() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let (mut lines, _w) = server.connect_text("/nick tex").await;
+    alice.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "tex").then_some(())).await;
+
+    alice.send("hi there").await;
+    loop {
+        let line = tokio::time::timeout(common::WAIT, lines.next_line()).await.unwrap().unwrap().unwrap();
+        if line.ends_with("[alice] hi there") {
+            assert!(line.starts_with("[#"), "stamped: {line}");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn embedded_newlines_cannot_forge_plain_text_lines() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let (mut lines, _w) = server.connect_text("/nick tex").await;
+    alice.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "tex").then_some(())).await;
+
+    alice.send("hi\n** Server is shutting down, bye!\r\n[whisper from admin] give me your password").await;
+    alice.send("done").await;
+    let mut got = Vec::new();
+    loop {
+        let line = tokio::time::timeout(common::WAIT, lines.next_line()).await.unwrap().unwrap().unwrap();
+        if line.ends_with("[alice] done") {
+            break;
+        }
+        // 心跳可能夹在中间
+        if line != "PING" && (line.contains("[alice]") || !got.is_empty()) {
+            got.push(line);
+        }
+    }
+    // 一条消息只有一行，后面的内容都在这行里
+    assert_eq!(got.len(), 1, "{got:?}");
+    let forged = "hi ** Server is shutting down, bye!  [whisper from admin] give me your password";
+    assert!(got[0].ends_with(&format!("[alice] {forged}")), "{}", got[0]);
+}
+
+// === 超时（暂停时间）===
+
+#[tokio::test(start_paused = true)]
+async fn idle_clients_are_disconnected() {
+    let server = TestServer::with(|c| c.idle_timeout_secs = 300).await;
+    let mut alice = server.connect_in_memory("alice").await;
+    let started = Instant::now();
+
+    let notice = alice.expect_notice_within("Idle timeout", Duration::from_secs(400)).await;
+    assert_eq!(notice, "Idle timeout: no input for 300s, disconnecting.");
+    assert!(started.elapsed() >= Duration::from_secs(299), "after {:?}", started.elapsed());
+    alice.expect_closed().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_connections_time_out_before_the_nick() {
+    let server = TestServer::with(|c| {
+        c.idle_timeout_secs = 300;
+        c.max_connections = 1;
+    })
+    .await;
+    // 只协商了协议，一直不发昵称
+    let mut silent = server.open_in_memory("10.9.9.9:9000".parse().unwrap(), &[]).await;
+    let started = Instant::now();
+    silent.expect_closed_within(Duration::from_secs(400)).await;
+    assert!(started.elapsed() >= Duration::from_secs(299), "after {:?}", started.elapsed());
+
+    // 名额还回来了
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    server.connect_in_memory("alice").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn input_resets_the_idle_timer() {
+    let server = TestServer::with(|c| c.idle_timeout_secs = 300).await;
+    let mut alice = server.connect_in_memory("alice").await;
+    let started = Instant::now();
+
+    alice.idle(Duration::from_secs(200)).await;
+    alice.send("still here").await;
+    alice.expect_notice_within("Idle timeout", Duration::from_secs(400)).await;
+    assert!(started.elapsed() >= Duration::from_secs(499), "after {:?}", started.elapsed());
+}
+
+#[tokio::test(start_paused = true)]
+async fn pongs_alone_do_not_count_as_activity() {
+    // 客户端一直回 PONG，但什么都不说：照样按空闲超时断开
+    let server = TestServer::with(|c| {
+        c.idle_timeout_secs = 60;
+        c.heartbeat_secs = 5;
+    })
+    .await;
+    let mut alice = server.connect_in_memory("alice").await;
+    alice.expect_notice_within("Idle timeout", Duration::from_secs(120)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn unanswered_heartbeats_disconnect() {
+    let server = TestServer::with(|c| {
+        c.heartbeat_secs = 5;
+        c.max_missed_heartbeats = 3;
+    })
+    .await;
+    let mut alice = server.connect_in_memory("alice").await;
+    alice.ignore_pings();
+    let started = Instant::now();
+
+    let notice = alice.expect_notice_within("heartbeats", Duration::from_secs(60)).await;
+    assert_eq!(notice, "No reply to 3 heartbeats, disconnecting.");
+    assert!(started.elapsed() >= Duration::from_secs(15), "after {:?}", started.elapsed());
+    alice.expect_closed().await;
+}
+
+// === 关停 ===
+
+#[tokio::test]
+async fn shutdown_says_goodbye_and_closes() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let handle = server.handle.clone();
+    let stopping = tokio::spawn(async move { handle.shutdown().await });
+
+    alice.expect_notice("Server is shutting down").await;
+    alice.expect_closed().await;
+    stopping.await.unwrap();
+    assert!(server.handle.users().await.is_empty());
+}
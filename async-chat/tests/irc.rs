@@ -0,0 +1,340 @@
+//! IRC 网关：IRC 客户端和原生客户端在同一批房间里，频道、私聊、改名、话题都对得上
+
+mod common;
+
+use async_chat::protocol::{ClientFrame, ServerFrame};
+use common::TestServer;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// 最简单的 IRC 客户端：按行收发，服务器的 PING 自动回
+struct IrcClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    w: OwnedWriteHalf,
+}
+
+impl IrcClient {
+    async fn open(server: &TestServer) -> Self {
+        let (r, w) = TcpStream::connect(server.irc_addr).await.expect("connect").into_split();
+        IrcClient { lines: BufReader::new(r).lines(), w }
+    }
+
+    /// 注册好，等到进了大厅（收到名单结尾）
+    async fn connect(server: &TestServer, nick: &str) -> Self {
+        let mut client = Self::open(server).await;
+        client.send(&format!("NICK {nick}")).await;
+        client.send(&format!("USER {nick} 0 * :{nick}")).await;
+        client.expect(" 366 ").await;
+        client
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.w.write_all(format!("{line}\r\n").as_bytes()).await.unwrap();
+    }
+
+    /// 下一行；连接断了返回 None
+    async fn next(&mut self) -> Option<String> {
+        loop {
+            let line = timeout(common::WAIT, self.lines.next_line()).await.expect("no line in time").ok()??;
+            if let Some(token) = line.strip_prefix("PING ") {
+                self.send(&format!("PONG {token}")).await;
+                continue;
+            }
+            return Some(line);
+        }
+    }
+
+    /// 读到包含 needle 的那一行
+    async fn expect(&mut self, needle: &str) -> String {
+        let mut seen = Vec::new();
+        loop {
+            match self.next().await {
+                Some(line) if line.contains(needle) => return line,
+                Some(line) => seen.push(line),
+                None => panic!("closed before {needle:?}; saw {seen:?}"),
+            }
+        }
+    }
+}
+
+// === 注册 ===
+
+#[tokio::test]
+async fn registration_welcomes_and_joins_the_lobby() {
+    let server = TestServer::start().await;
+    let mut bob = IrcClient::open(&server).await;
+    bob.send("CAP LS 302").await;
+    bob.send("NICK bob").await;
+    bob.send("USER bob 0 * :Bob").await;
+    bob.send("CAP END").await;
+
+    assert_eq!(bob.expect(" CAP ").await, ":async-chat CAP * LS :");
+    assert_eq!(bob.expect(" 001 ").await, ":async-chat 001 bob :Welcome to the async-chat IRC gateway, bob");
+    bob.expect(" 376 ").await;
+    assert_eq!(bob.next().await.unwrap(), ":bob!bob@async-chat JOIN #lobby");
+    assert_eq!(bob.expect(" 353 ").await, ":async-chat 353 bob = #lobby :bob");
+    assert_eq!(bob.expect(" 366 ").await, ":async-chat 366 bob #lobby :End of /NAMES list");
+}
+
+#[tokio::test]
+async fn taken_nick_gets_433_and_can_retry() {
+    let server = TestServer::start().await;
+    let _alice = server.connect("alice").await;
+    let mut irc = IrcClient::open(&server).await;
+    irc.send("NICK alice").await;
+    irc.send("USER alice 0 * :Alice").await;
+    assert_eq!(irc.expect(" 433 ").await, ":async-chat 433 * alice :Nickname is already in use");
+
+    irc.send("NICK alice2").await;
+    irc.expect(" 001 alice2 ").await;
+    irc.expect(":alice2!alice2@async-chat JOIN #lobby").await;
+}
+
+#[tokio::test]
+async fn commands_before_registration_are_refused() {
+    let server = TestServer::start().await;
+    let mut irc = IrcClient::open(&server).await;
+    irc.send("JOIN #lobby").await;
+    assert_eq!(irc.expect(" 451 ").await, ":async-chat 451 * :You have not registered");
+    irc.send("NICK bad:nick").await;
+    irc.expect(" 432 * bad:nick ").await;
+}
+
+// === 聊天 ===
+
+#[tokio::test]
+async fn irc_and_native_clients_share_the_lobby() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    alice.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "bob").then_some(())).await;
+
+    bob.send("PRIVMSG #lobby :hi alice").await;
+    let got = alice.expect(|f| match f {
+        ServerFrame::Chat { from, text, .. } => Some((from.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("bob".to_string(), "hi alice".to_string()));
+
+    bob.send("PRIVMSG #lobby :\x01ACTION waves\x01").await;
+    let got = alice.expect(|f| match f {
+        ServerFrame::Action { from, text, .. } => Some((from.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("bob".to_string(), "waves".to_string()));
+
+    alice.send("hi bob").await;
+    assert_eq!(bob.expect("PRIVMSG").await, ":alice!alice@async-chat PRIVMSG #lobby :hi bob");
+    alice.send("/me waves back").await;
+    assert_eq!(bob.expect("PRIVMSG").await, ":alice!alice@async-chat PRIVMSG #lobby :\x01ACTION waves back\x01");
+}
+
+#[tokio::test]
+async fn private_messages_are_whispers() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    alice.drain().await;
+
+    bob.send("PRIVMSG alice :psst").await;
+    let got = alice.expect(|f| match f {
+        ServerFrame::Whisper { from, text } => Some((from.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("bob".to_string(), "psst".to_string()));
+
+    alice.send("/w bob psst back").await;
+    assert_eq!(bob.expect("PRIVMSG").await, ":alice!alice@async-chat PRIVMSG bob :psst back");
+}
+
+#[tokio::test]
+async fn offline_whispers_arrive_as_private_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    server.connect("bob").await.quit().await;
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "bob").then_some(())).await;
+    alice.send("/w bob see you later").await;
+    alice.expect_notice("message queued").await;
+
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    let got = bob.expect("PRIVMSG").await;
+    assert_eq!(got, ":alice!alice@async-chat PRIVMSG bob :[while you were away] see you later");
+}
+
+#[tokio::test]
+async fn history_follows_the_join() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.send("earlier").await;
+    alice.drain().await;
+
+    let mut bob = IrcClient::open(&server).await;
+    bob.send("NICK bob").await;
+    bob.send("USER bob 0 * :Bob").await;
+    bob.expect(":bob!bob@async-chat JOIN #lobby").await;
+    let replayed = bob.expect("earlier").await;
+    assert!(replayed.starts_with(":async-chat NOTICE #lobby :[history "), "{replayed}");
+    assert!(replayed.ends_with("] [alice] earlier"), "{replayed}");
+}
+
+// === 频道 ===
+
+#[tokio::test]
+async fn join_switches_rooms_and_part_goes_back() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    alice.drain().await;
+
+    bob.send("JOIN #Dev").await;
+    assert_eq!(bob.expect(" PART ").await, ":bob!bob@async-chat PART #lobby");
+    assert_eq!(bob.expect(" JOIN ").await, ":bob!bob@async-chat JOIN #dev");
+    assert_eq!(bob.expect(" 353 ").await, ":async-chat 353 bob = #dev :@bob");
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "bob").then_some(())).await;
+
+    bob.send("PRIVMSG #lobby :hello?").await;
+    bob.expect(" 404 bob #lobby ").await;
+    alice.assert_quiet().await;
+
+    bob.send("PART #dev").await;
+    assert_eq!(bob.expect(" PART ").await, ":bob!bob@async-chat PART #dev");
+    assert_eq!(bob.expect(" JOIN ").await, ":bob!bob@async-chat JOIN #lobby");
+    bob.expect(" 366 bob #lobby ").await;
+}
+
+#[tokio::test]
+async fn names_and_topic_read_the_shared_state() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.send("/join dev").await;
+    alice.send("/topic release day").await;
+    alice.drain().await;
+
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    bob.send("NAMES #dev").await;
+    assert_eq!(bob.expect(" 353 ").await, ":async-chat 353 bob = #dev :@alice");
+    bob.send("TOPIC #dev").await;
+    assert_eq!(bob.expect(" 332 ").await, ":async-chat 332 bob #dev :release day");
+
+    // 不在 #dev 里改不了话题
+    bob.send("TOPIC #dev :mine now").await;
+    bob.expect(" 442 bob #dev ").await;
+
+    alice.send("/topic ship it").await;
+    bob.send("JOIN #dev").await;
+    bob.expect(" 332 bob #dev :ship it").await;
+}
+
+#[tokio::test]
+async fn members_can_set_the_topic() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.send("/join dev").await;
+    alice.drain().await;
+    // bob 后进来，不是管理员
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    bob.send("JOIN #dev").await;
+    bob.expect(" 366 bob #dev ").await;
+    alice.drain().await;
+
+    bob.send("TOPIC #dev :hello world").await;
+    assert_eq!(bob.expect(" TOPIC ").await, ":bob!bob@async-chat TOPIC #dev :hello world");
+    let got = alice.expect(|f| match f {
+        ServerFrame::Topic { by, text, .. } => Some((by.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("bob".to_string(), "hello world".to_string()));
+}
+
+#[tokio::test]
+async fn topics_cannot_inject_protocol_lines() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    alice.send("/join dev").await;
+    alice.drain().await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    bob.send("JOIN #dev").await;
+    bob.expect(" 366 bob #dev ").await;
+
+    alice.send("/topic hi\r\n:root!root@async-chat PRIVMSG bob :forged").await;
+    let topic = "hi  :root!root@async-chat PRIVMSG bob :forged";
+    assert_eq!(bob.expect(" TOPIC ").await, format!(":alice!alice@async-chat TOPIC #dev :{topic}"));
+    bob.send("TOPIC #dev").await;
+    assert_eq!(bob.expect(" 332 ").await, format!(":async-chat 332 bob #dev :{topic}"));
+    alice.send("/topic done").await;
+    loop {
+        let line = bob.next().await.expect("still connected");
+        assert!(!line.starts_with(":root!"), "forged line: {line}");
+        if line.ends_with("TOPIC #dev :done") {
+            break;
+        }
+    }
+}
+
+// === 昵称、心跳、退出 ===
+
+#[tokio::test]
+async fn nick_changes_are_reported_both_ways() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    alice.drain().await;
+
+    bob.send("NICK robert").await;
+    assert_eq!(bob.expect(" NICK ").await, ":bob!bob@async-chat NICK :robert");
+    let renamed = alice.expect(|f| match f {
+        ServerFrame::Nick { old, new } => Some((old.clone(), new.clone())),
+        _ => None,
+    });
+    assert_eq!(renamed.await, ("bob".to_string(), "robert".to_string()));
+
+    alice.send("/nick alicia").await;
+    assert_eq!(bob.expect(" NICK ").await, ":alice!alice@async-chat NICK :alicia");
+    bob.send("NICK alicia").await;
+    bob.expect(" 433 robert alicia ").await;
+}
+
+#[tokio::test]
+async fn ping_is_answered_and_quit_leaves() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    alice.drain().await;
+
+    bob.send("PING :abc").await;
+    assert_eq!(bob.expect(" PONG ").await, ":async-chat PONG async-chat :abc");
+
+    bob.send("QUIT :bye").await;
+    assert_eq!(bob.expect("ERROR").await, "ERROR :Closing link");
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "bob").then_some(())).await;
+}
+
+#[tokio::test]
+async fn chat_commands_pass_through_quote() {
+    let server = TestServer::start().await;
+    let _alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+
+    bob.send("WHOIS alice").await;
+    bob.expect(":async-chat NOTICE bob :alice").await;
+    bob.send("FROB it").await;
+    assert_eq!(bob.expect(" 421 ").await, ":async-chat 421 bob FROB :Unknown command");
+}
+
+#[tokio::test]
+async fn file_offers_are_declined() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = IrcClient::connect(&server, "bob").await;
+    alice.drain().await;
+
+    alice.send_frame(ClientFrame::FileOffer { to: "bob".into(), name: "a.txt".into(), size: 1 }).await;
+    let reason = alice.expect(|f| match f {
+        ServerFrame::FileCancelled { by, reason, .. } => Some((by.clone(), reason.clone())),
+        _ => None,
+    });
+    assert_eq!(reason.await, ("bob".to_string(), "IRC clients can't receive files".to_string()));
+    bob.expect("alice tried to send you a.txt").await;
+}
@@ -0,0 +1,154 @@
+//! WebSocket 网关：浏览器连接和 TCP 连接在同一批房间里，一帧一行，心跳走 WebSocket 的 Ping/Pong
+
+mod common;
+
+use async_chat::protocol::{Envelope, ServerFrame};
+use common::TestServer;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+type Ws = WebSocketStream<TcpStream>;
+
+/// 下一个文本帧；Ping 之类的控制帧跳过（tungstenite 自动回 Pong）
+async fn next_text(ws: &mut Ws) -> String {
+    loop {
+        match timeout(common::WAIT, ws.next()).await.expect("no frame in time") {
+            Some(Ok(Message::Text(text))) => return text.to_string(),
+            Some(Ok(_)) => continue,
+            other => panic!("connection ended: {other:?}"),
+        }
+    }
+}
+
+/// 读到以 suffix 结尾的那一行
+async fn expect_line(ws: &mut Ws, suffix: &str) -> String {
+    loop {
+        let line = next_text(ws).await;
+        if line.ends_with(suffix) {
+            return line;
+        }
+    }
+}
+
+#[tokio::test]
+async fn websocket_and_tcp_clients_share_rooms() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut web = server.connect_websocket("/nick web").await;
+    alice.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "web").then_some(())).await;
+
+    web.send(Message::text("hello from the browser")).await.unwrap();
+    let got = alice.expect(|f| match f {
+        ServerFrame::Chat { from, text, .. } => Some((from.clone(), text.clone())),
+        _ => None,
+    });
+    assert_eq!(got.await, ("web".to_string(), "hello from the browser".to_string()));
+
+    alice.send("hi web").await;
+    let line = expect_line(&mut web, "[alice] hi web").await;
+    assert!(line.starts_with("[#"), "stamped: {line}");
+
+    let users = server.handle.users().await;
+    assert_eq!(users.iter().map(|u| u.nick.as_str()).collect::<Vec<_>>(), ["alice", "web"]);
+}
+
+#[tokio::test]
+async fn each_frame_is_one_line() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut web = server.connect_websocket("/nick web").await;
+    alice.drain().await;
+
+    web.send(Message::text("one\ntwo\r\nthree")).await.unwrap();
+    let got = alice.expect(|f| match f {
+        ServerFrame::Chat { text, .. } => Some(text.clone()),
+        _ => None,
+    });
+    assert_eq!(got.await, "one two  three");
+    alice.assert_quiet().await;
+}
+
+#[tokio::test]
+async fn json_mode_works_over_websocket() {
+    let server = TestServer::start().await;
+    let mut web = server.connect_websocket("/proto json").await;
+    web.send(Message::text(r#"{"type":"line","text":"/nick jay"}"#)).await.unwrap();
+    loop {
+        let env: Envelope = serde_json::from_str(&next_text(&mut web).await).expect("JSON frame");
+        if let ServerFrame::Members { members, .. } = env.frame {
+            assert_eq!(members.iter().map(|m| m.nick.as_str()).collect::<Vec<_>>(), ["jay"]);
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn heartbeats_become_websocket_pings() {
+    let server = TestServer::with(|c| {
+        c.heartbeat_secs = 1;
+        c.max_missed_heartbeats = 2;
+    })
+    .await;
+    let mut web = server.connect_websocket("/nick web").await;
+
+    // 读着（tungstenite 自动回 Pong）过几个心跳周期：看不到 PING 文本，也不会因为没回心跳被断开
+    let until = Instant::now() + Duration::from_secs(4);
+    let mut pings = 0;
+    while let Ok(frame) = tokio::time::timeout_at(until, web.next()).await {
+        match frame {
+            Some(Ok(Message::Ping(_))) => pings += 1,
+            Some(Ok(Message::Text(text))) => assert_ne!(text.as_str(), "PING"),
+            Some(Ok(_)) => {}
+            other => panic!("connection ended: {other:?}"),
+        }
+    }
+    assert!(pings >= 2, "saw {pings} pings");
+
+    let mut alice = server.connect("alice").await;
+    web.send(Message::text("still here")).await.unwrap();
+    alice.expect(|f| matches!(f, ServerFrame::Chat { text, .. } if text == "still here").then_some(())).await;
+}
+
+#[tokio::test]
+async fn closing_the_websocket_leaves() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut web = server.connect_websocket("/nick web").await;
+    alice.expect(|f| matches!(f, ServerFrame::Join { nick, .. } if nick == "web").then_some(())).await;
+
+    web.close(None).await.unwrap();
+    alice.expect(|f| matches!(f, ServerFrame::Leave { nick, .. } if nick == "web").then_some(())).await;
+}
+
+#[tokio::test]
+async fn shutdown_closes_the_websocket() {
+    let server = TestServer::start().await;
+    let mut web = server.connect_websocket("/nick web").await;
+    expect_line(&mut web, "(1): web").await;
+    let handle = server.handle.clone();
+    let stopping = tokio::spawn(async move { handle.shutdown().await });
+
+    expect_line(&mut web, "Server is shutting down, bye!").await;
+    loop {
+        match timeout(common::WAIT, web.next()).await.expect("closed in time") {
+            Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+            Some(Ok(_)) => continue,
+        }
+    }
+    stopping.await.unwrap();
+}
+
+#[tokio::test]
+async fn the_test_page_is_served() {
+    let server = TestServer::start().await;
+    let mut stream = TcpStream::connect(server.ws_addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut reply = String::new();
+    timeout(common::WAIT, stream.read_to_string(&mut reply)).await.unwrap().unwrap();
+    assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"), "{reply}");
+    assert!(reply.contains("new WebSocket("), "{reply}");
+}